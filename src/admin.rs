@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use meshtastic::protobufs::{Channel, Config, admin_message::ConfigType};
+
+use crate::mesh::service::{
+    AdminRequest, AdminResponse, Handler, Service, ServiceConfig, Transport,
+};
+use crate::utils::parse_node_id;
+
+// Time to wait for each response of the node
+const ADMIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ConfigSection {
+    Device,
+    Position,
+    Power,
+    Network,
+    Display,
+    Lora,
+    Bluetooth,
+    Security,
+}
+
+impl ConfigSection {
+    fn config_type(self) -> ConfigType {
+        match self {
+            ConfigSection::Device => ConfigType::DeviceConfig,
+            ConfigSection::Position => ConfigType::PositionConfig,
+            ConfigSection::Power => ConfigType::PowerConfig,
+            ConfigSection::Network => ConfigType::NetworkConfig,
+            ConfigSection::Display => ConfigType::DisplayConfig,
+            ConfigSection::Lora => ConfigType::LoraConfig,
+            ConfigSection::Bluetooth => ConfigType::BluetoothConfig,
+            ConfigSection::Security => ConfigType::SecurityConfig,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AdminCommand {
+    /// Print the long and short names of the node
+    GetOwner,
+    /// Change the long or short name of the node
+    SetOwner {
+        #[arg(long)]
+        long_name: Option<String>,
+        #[arg(long)]
+        short_name: Option<String>,
+    },
+    /// Print a config section of the node as JSON
+    GetConfig {
+        #[arg(value_enum)]
+        section: ConfigSection,
+    },
+    /// Change a config section of the node, from JSON as printed by `get-config`
+    SetConfig { file: PathBuf },
+    /// Print the settings of a channel of the node as JSON, 0 being the primary one
+    GetChannel { index: u32 },
+    /// Change a channel of the node, from JSON as printed by `get-channel`
+    SetChannel { file: PathBuf },
+    /// Reboot the node
+    Reboot {
+        #[arg(long, default_value = "5")]
+        seconds: i32,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct AdminArgs {
+    /// Node to administer, by short name or `!aabbccdd`
+    node: String,
+    /// Radio to send the requests through, defaults to the environment configuration
+    #[arg(long)]
+    transport: Option<Transport>,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+/// `admin` command of the REPL
+#[derive(Debug, Parser)]
+#[command(name = "admin", no_binary_name = true)]
+struct ReplArgs {
+    node: String,
+    #[command(subcommand)]
+    command: AdminCommand,
+}
+
+/// Sends the requests of `command` to `node` through the radio of the handler
+/// and prints the outcome.
+pub async fn run(handler: &mut Handler, node: &str, command: AdminCommand) -> Result<()> {
+    let node_id = match parse_node_id(node) {
+        Ok(num) if node.starts_with('!') => num,
+        _ => match handler.state.read().await.get_node_id_by_short_name(node) {
+            Some(node_id) => node_id,
+            None => bail!("Node '{node}' not found"),
+        },
+    };
+    match command {
+        AdminCommand::GetOwner => {
+            if let Some(AdminResponse::Owner(user)) = handler
+                .admin(node_id, AdminRequest::GetOwner, ADMIN_TIMEOUT)
+                .await?
+            {
+                println!("{} ({})", user.long_name, user.short_name);
+            }
+        }
+        AdminCommand::SetOwner {
+            long_name,
+            short_name,
+        } => {
+            if long_name.is_none() && short_name.is_none() {
+                bail!("Nothing to change, use --long-name or --short-name");
+            }
+            // The node replaces the whole owner, so the current one is changed
+            let Some(AdminResponse::Owner(mut user)) = handler
+                .admin(node_id, AdminRequest::GetOwner, ADMIN_TIMEOUT)
+                .await?
+            else {
+                bail!("Unexpected admin response");
+            };
+            if let Some(long_name) = long_name {
+                user.long_name = long_name;
+            }
+            if let Some(short_name) = short_name {
+                user.short_name = short_name;
+            }
+            handler
+                .admin(node_id, AdminRequest::SetOwner(user.clone()), ADMIN_TIMEOUT)
+                .await?;
+            println!("Owner set to {} ({})", user.long_name, user.short_name);
+        }
+        AdminCommand::GetConfig { section } => {
+            let request = AdminRequest::GetConfig(section.config_type());
+            if let Some(AdminResponse::Config(config)) =
+                handler.admin(node_id, request, ADMIN_TIMEOUT).await?
+            {
+                println!("{}", serde_json::to_string_pretty(&config)?);
+            }
+        }
+        AdminCommand::SetConfig { file } => {
+            let config: Config = serde_json::from_slice(&std::fs::read(file)?)?;
+            handler
+                .admin(node_id, AdminRequest::SetConfig(config), ADMIN_TIMEOUT)
+                .await?;
+            println!("Config set, the node may reboot to apply it");
+        }
+        AdminCommand::GetChannel { index } => {
+            let request = AdminRequest::GetChannel(index);
+            if let Some(AdminResponse::Channel(channel)) =
+                handler.admin(node_id, request, ADMIN_TIMEOUT).await?
+            {
+                println!("{}", serde_json::to_string_pretty(&channel)?);
+            }
+        }
+        AdminCommand::SetChannel { file } => {
+            let channel: Channel = serde_json::from_slice(&std::fs::read(file)?)?;
+            let index = channel.index;
+            handler
+                .admin(node_id, AdminRequest::SetChannel(channel), ADMIN_TIMEOUT)
+                .await?;
+            println!("Channel {index} set");
+        }
+        AdminCommand::Reboot { seconds } => {
+            handler
+                .admin(node_id, AdminRequest::Reboot(seconds), ADMIN_TIMEOUT)
+                .await?;
+            println!("Rebooting in {seconds}s");
+        }
+    }
+    Ok(())
+}
+
+/// Runs a REPL line, `admin <node> <command> [args]`.
+pub async fn repl(handler: &mut Handler, line: &[&str]) -> Result<()> {
+    let args = ReplArgs::try_parse_from(line)?;
+    run(handler, &args.node, args.command).await
+}
+
+pub async fn admin(args: AdminArgs) -> Result<()> {
+    let transport = match args.transport {
+        Some(transport) => transport,
+        None => Transport::from_env()?,
+    };
+    let mut handler =
+        Service::from_transport_with_config(&transport, ServiceConfig::one_shot()).await?;
+    handler.wait_for_boot_ready(30).await?;
+    let result = run(&mut handler, &args.node, args.command).await;
+    handler.finish().await;
+    result
+}
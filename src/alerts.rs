@@ -0,0 +1,233 @@
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+
+use crate::mesh::service::NodeTelemetry;
+use crate::utils::parse_node_id;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Battery,
+    Voltage,
+    ChannelUtilization,
+    AirUtilTx,
+    Temperature,
+}
+
+impl FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "battery" => Metric::Battery,
+            "voltage" => Metric::Voltage,
+            "channel_utilization" => Metric::ChannelUtilization,
+            "air_util_tx" => Metric::AirUtilTx,
+            "temperature" => Metric::Temperature,
+            _ => bail!("Unknown metric '{s}'"),
+        })
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Metric::Battery => "battery",
+            Metric::Voltage => "voltage",
+            Metric::ChannelUtilization => "channel_utilization",
+            Metric::AirUtilTx => "air_util_tx",
+            Metric::Temperature => "temperature",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Metric {
+    fn value(self, sample: &NodeTelemetry) -> Option<f32> {
+        match self {
+            // Over 100 means powered externally
+            Metric::Battery => sample
+                .battery_level
+                .filter(|level| *level <= 100)
+                .map(|level| level as f32),
+            Metric::Voltage => sample.voltage,
+            Metric::ChannelUtilization => sample.channel_utilization,
+            Metric::AirUtilTx => sample.air_util_tx,
+            Metric::Temperature => sample.temperature,
+        }
+    }
+}
+
+/// Threshold on a metric, parsed from `[<node>:]<metric><|><threshold>`,
+/// e.g. `battery<20` or `!a1b2c3d4:temperature>60`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    // Node the rule applies to, every node when missing
+    pub node: Option<u32>,
+    pub metric: Metric,
+    // Alert when the value goes below the threshold, otherwise above it
+    pub below: bool,
+    pub threshold: f32,
+}
+
+impl FromStr for AlertRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (node, rule) = match s.split_once(':') {
+            Some((node, rule)) => (Some(parse_node_id(node)?), rule),
+            None => (None, s),
+        };
+        let (metric, threshold, below) = if let Some((metric, threshold)) = rule.split_once('<') {
+            (metric, threshold, true)
+        } else if let Some((metric, threshold)) = rule.split_once('>') {
+            (metric, threshold, false)
+        } else {
+            bail!("Missing '<' or '>' in alert rule '{s}'");
+        };
+        Ok(AlertRule {
+            node,
+            metric: metric.trim().parse()?,
+            below,
+            threshold: threshold.trim().parse()?,
+        })
+    }
+}
+
+impl AlertRule {
+    fn breached(&self, value: f32) -> bool {
+        if self.below {
+            value < self.threshold
+        } else {
+            value > self.threshold
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub rule: AlertRule,
+    pub value: f32,
+    // The value is back within the threshold
+    pub recovered: bool,
+}
+
+impl Alert {
+    pub fn message(&self, node_name: &str) -> String {
+        let (icon, state) = if self.recovered {
+            ("✅", "back")
+        } else if self.rule.below {
+            ("🪫", "below")
+        } else {
+            ("🔥", "above")
+        };
+        let threshold = if self.recovered {
+            format!(
+                "{} {}",
+                if self.rule.below { ">=" } else { "<=" },
+                self.rule.threshold
+            )
+        } else {
+            self.rule.threshold.to_string()
+        };
+        format!(
+            "{} {} {} {} {} {}",
+            icon, node_name, self.rule.metric, self.value, state, threshold
+        )
+    }
+}
+
+/// Raises an alert when a node telemetry crosses a rule threshold, and
+/// another one when it recovers.
+#[derive(Debug, Default)]
+pub struct TelemetryAlerts {
+    rules: Vec<AlertRule>,
+    // (node, rule index) currently breached
+    active: HashSet<(u32, usize)>,
+}
+
+impl TelemetryAlerts {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            active: HashSet::new(),
+        }
+    }
+
+    /// Rules of `TELEMETRY_ALERTS`, separated by commas.
+    pub fn from_env() -> Result<Self> {
+        let Ok(rules) = std::env::var("TELEMETRY_ALERTS") else {
+            return Ok(Self::default());
+        };
+        let rules = rules
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(|rule| rule.trim().parse())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(rules))
+    }
+
+    pub fn check(&mut self, node_id: u32, sample: &NodeTelemetry) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.node.is_some_and(|node| node != node_id) {
+                continue;
+            }
+            let Some(value) = rule.metric.value(sample) else {
+                continue;
+            };
+            let breached = rule.breached(value);
+            let was_breached = if breached {
+                !self.active.insert((node_id, index))
+            } else {
+                self.active.remove(&(node_id, index))
+            };
+            if breached != was_breached {
+                alerts.push(Alert {
+                    rule: rule.clone(),
+                    value,
+                    recovered: !breached,
+                });
+            }
+        }
+        alerts
+    }
+}
+
+#[test]
+fn test_alerts() {
+    let rules = vec![
+        "!000000aa:battery<20".parse().unwrap(),
+        "temperature > 60".parse().unwrap(),
+    ];
+    assert!("battery=20".parse::<AlertRule>().is_err());
+    assert!("humidity>90".parse::<AlertRule>().is_err());
+
+    let mut alerts = TelemetryAlerts::new(rules);
+    let battery = |level| NodeTelemetry {
+        battery_level: Some(level),
+        ..Default::default()
+    };
+    assert!(alerts.check(0xaa, &battery(50)).is_empty());
+    assert!(alerts.check(0xbb, &battery(10)).is_empty());
+
+    let low = alerts.check(0xaa, &battery(18));
+    assert_eq!(low.len(), 1);
+    assert!(!low[0].recovered);
+    assert_eq!(low[0].message("solar"), "🪫 solar battery 18 below 20");
+    // No repeated alert while the battery stays low
+    assert!(alerts.check(0xaa, &battery(15)).is_empty());
+    // Powered externally says nothing about the battery
+    assert!(alerts.check(0xaa, &battery(101)).is_empty());
+
+    let back = alerts.check(0xaa, &battery(25));
+    assert_eq!(back.len(), 1);
+    assert!(back[0].recovered);
+    assert_eq!(back[0].message("solar"), "✅ solar battery 25 back >= 20");
+
+    let hot = NodeTelemetry {
+        temperature: Some(65.0),
+        ..Default::default()
+    };
+    assert_eq!(alerts.check(0xbb, &hot).len(), 1);
+}
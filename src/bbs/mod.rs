@@ -1,14 +1,26 @@
 use mini_moka::sync::Cache;
-use std::time::{Duration, Instant};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Result, bail};
+use tokio::signal;
 
-mod storage;
+pub mod storage;
 
 use crate::bbs::storage::ChannelMessage;
 use crate::bbs::storage::Storage;
+use crate::bbs::storage::Subscription;
 use crate::bbs::storage::User;
 use crate::bbs::storage::UserPkHash;
+use crate::mesh::service::{Handler, State, Status, TextMessageStatus};
+use crate::utils::parse_node_id;
+
+// Minimum time between two subscription notifications sent to the same node
+const NOTIFY_THROTTLE: Duration = Duration::from_secs(1800);
+// Time to wait for the response of a traceroute
+const TRACE_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[derive(Debug, Clone)]
 struct Session {
@@ -17,9 +29,51 @@ struct Session {
     current_channel: u32,
 }
 
-struct BBS<S: Storage> {
+pub struct BBS<S: Storage> {
     storage: S,
     sessions: Cache<UserPkHash, Session>,
+    notified: Cache<u32, Instant>,
+    // Nodes allowed to run the sysop commands
+    sysops: HashSet<u32>,
+    // Traced node to the nodes that asked for it and the channel they asked in
+    traces: Cache<u32, Vec<(u32, u32)>>,
+    // Traceroutes to send on behalf of `handle`
+    trace_requests: Vec<u32>,
+    // Direct messages to relay on behalf of `handle`, destination and text
+    relays: Vec<(u32, String)>,
+}
+
+fn now_ts() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// Users are identified by their node public key, nodes without one
+/// fall back to their node number.
+fn user_pk_hash(user: Option<&meshtastic::protobufs::User>, node_id: u32) -> UserPkHash {
+    let mut pk_hash = [0u8; 32];
+    match user {
+        Some(user) if user.public_key.len() == 32 => pk_hash.copy_from_slice(&user.public_key),
+        _ => pk_hash[..4].copy_from_slice(&node_id.to_le_bytes()),
+    }
+    pk_hash
+}
+
+/// Node ids of `BBS_SYSOPS`, separated by commas.
+fn sysops_from_env() -> HashSet<u32> {
+    let Ok(sysops) = std::env::var("BBS_SYSOPS") else {
+        return HashSet::new();
+    };
+    sysops
+        .split(',')
+        .filter(|id| !id.trim().is_empty())
+        .filter_map(|id| match parse_node_id(id.trim()) {
+            Ok(id) => Some(id),
+            Err(err) => {
+                log::warn!("Ignoring sysop '{}': {}", id, err);
+                None
+            }
+        })
+        .collect()
 }
 
 impl<S: Storage> BBS<S> {
@@ -30,6 +84,17 @@ impl<S: Storage> BBS<S> {
                 .max_capacity(1024)
                 .time_to_live(Duration::from_secs(3600))
                 .build(),
+            notified: Cache::builder()
+                .max_capacity(1024)
+                .time_to_live(NOTIFY_THROTTLE)
+                .build(),
+            sysops: sysops_from_env(),
+            traces: Cache::builder()
+                .max_capacity(1024)
+                .time_to_live(TRACE_TIMEOUT)
+                .build(),
+            trace_requests: Vec::new(),
+            relays: Vec::new(),
         }
     }
     pub async fn init(&mut self) -> Result<()> {
@@ -40,6 +105,7 @@ impl<S: Storage> BBS<S> {
     }
     pub async fn handle(
         &mut self,
+        mesh: &State,
         user_pk_hash: [u8; 32],
         radio_userid: u32,
         command: &str,
@@ -95,7 +161,8 @@ impl<S: Storage> BBS<S> {
             }
             "/post" if command.len() == 2 => {
                 let message = ChannelMessage {
-                    ts: 0,
+                    mid: 0,
+                    ts: now_ts(),
                     uid: session.user_id,
                     text: command[1].to_string(),
                 };
@@ -106,7 +173,225 @@ impl<S: Storage> BBS<S> {
 
                 return Ok("Ack".into());
             }
+            "/sub" if command.len() == 2 => {
+                let Ok(channel) = self.storage.get_channel_by_name(command[1]).await else {
+                    bail!("Channel not found");
+                };
+                // Only the messages posted from now on are notified
+                let now = now_ts();
+                let posted = self
+                    .storage
+                    .get_messages(channel.cid, now as u32, u32::MAX)
+                    .await?;
+                self.storage
+                    .add_subscription(&Subscription {
+                        uid: session.user_id,
+                        cid: channel.cid,
+                        last_ts: now,
+                        last_mid: posted.iter().map(|m| m.mid).max().unwrap_or(0),
+                    })
+                    .await?;
+                return Ok("Ack".into());
+            }
+            "/unsub" if command.len() == 2 => {
+                let Ok(channel) = self.storage.get_channel_by_name(command[1]).await else {
+                    bail!("Channel not found");
+                };
+                self.storage
+                    .rm_subscription(session.user_id, channel.cid)
+                    .await?;
+                return Ok("Ack".into());
+            }
+            "/where" if command.len() == 2 => {
+                let state = mesh.read().await;
+                let Some(node_id) = state.get_node_id_by_short_name(command[1]) else {
+                    bail!("Node not found");
+                };
+                let Some(position) = state.last_position(node_id) else {
+                    bail!("No position for {}", command[1]);
+                };
+                return Ok(format!(
+                    "{} {}",
+                    command[1],
+                    position.describe(now_ts() as i64)
+                ));
+            }
+            "/tele" if command.len() == 2 => {
+                let state = mesh.read().await;
+                let Some(node_id) = state.get_node_id_by_short_name(command[1]) else {
+                    bail!("Node not found");
+                };
+                let Some(telemetry) = state.latest_telemetry(node_id) else {
+                    bail!("No telemetry for {}", command[1]);
+                };
+                return Ok(format!("{} {}", command[1], telemetry.describe()));
+            }
+            "/trace" if command.len() == 2 => {
+                if !self.sysops.contains(&radio_userid) {
+                    bail!("Only for sysops");
+                }
+                let Some(node_id) = mesh.read().await.get_node_id_by_short_name(command[1]) else {
+                    bail!("Node not found");
+                };
+                self.trace_requests.push(node_id);
+                return Ok(format!("Tracing {}", command[1]));
+            }
+            "/msg" if command.len() == 2 => {
+                let Some((name, text)) = command[1].split_once(' ') else {
+                    bail!("Usage: /msg <shortname> <message>");
+                };
+                let state = mesh.read().await;
+                let Some(node_id) = state.get_node_id_by_short_name(name) else {
+                    bail!("Node not found");
+                };
+                let text = format!("{}: {}", state.node_label(radio_userid), text);
+                self.relays.push((node_id, text));
+                return Ok("Ack".into());
+            }
+            "/pending" if command.len() == 1 => {
+                let state = mesh.read().await;
+                let pending = state.outbox.pending_from(radio_userid);
+                if pending.is_empty() {
+                    return Ok("Nothing pending".into());
+                }
+                let list = pending
+                    .iter()
+                    .map(|m| {
+                        format!(
+                            "to {}: {} ({} tries)",
+                            state.node_label(m.to),
+                            m.text,
+                            m.attempts
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                return Ok(list);
+            }
             _ => bail!("Unknown command"),
         }
     }
+
+    /// Summary of the messages posted in the channels the node is subscribed
+    /// to since the last notification, throttled by `NOTIFY_THROTTLE`.
+    pub async fn notification_for(&mut self, radio_userid: u32) -> Result<Option<String>> {
+        if self.notified.contains_key(&radio_userid) {
+            return Ok(None);
+        }
+        let Ok(user) = self.storage.get_user_by_radio_userid(radio_userid).await else {
+            return Ok(None);
+        };
+
+        let channels = self.storage.get_channels().await?;
+        let mut news = Vec::new();
+        for mut sub in self.storage.get_subscriptions(user.uid).await? {
+            let from_ts = sub.last_ts.min(u32::MAX as u64) as u32;
+            let posted: Vec<_> = self
+                .storage
+                .get_messages(sub.cid, from_ts, u32::MAX)
+                .await?
+                .into_iter()
+                .filter(|m| m.mid > sub.last_mid)
+                .collect();
+            let messages: Vec<_> = posted.iter().filter(|m| m.uid != user.uid).collect();
+            if messages.is_empty() {
+                continue;
+            }
+            let name = channels
+                .iter()
+                .find(|c| c.cid == sub.cid)
+                .map(|c| c.name.as_str())
+                .unwrap_or("?");
+            news.push(format!("{} new in #{}", messages.len(), name));
+
+            if let Some(last) = posted.iter().max_by_key(|m| m.mid) {
+                sub.last_ts = last.ts;
+                sub.last_mid = last.mid;
+            }
+            self.storage.update_subscription(&sub).await?;
+        }
+
+        if news.is_empty() {
+            return Ok(None);
+        }
+        self.notified.insert(radio_userid, Instant::now());
+        Ok(Some(news.join(", ")))
+    }
+
+    /// Replies to a direct message, sending the traceroutes and the direct
+    /// messages it asked for, or delivers the results of a previous one.
+    async fn handle_status(&mut self, handler: &Handler, status: Status) -> Result<()> {
+        match status {
+            Status::NewMessage(id) => {
+                let (msg, pk_hash) = {
+                    let state = handler.state.read().await;
+                    let Some(msg) = state.msg(id).await else {
+                        return Ok(());
+                    };
+                    if msg.to != state.my_node_num().await
+                        || !matches!(msg.status, TextMessageStatus::Recieved)
+                    {
+                        return Ok(());
+                    }
+                    let pk_hash = user_pk_hash(state.nodes.user(msg.from), msg.from);
+                    (msg, pk_hash)
+                };
+                let reply = match self
+                    .handle(&handler.state, pk_hash, msg.from, &msg.text)
+                    .await
+                {
+                    Ok(reply) => reply,
+                    Err(err) => format!("Error: {}", err),
+                };
+                handler.send_text(reply, msg.from, msg.channel).await?;
+                for (to, text) in std::mem::take(&mut self.relays) {
+                    handler.relay_text(text, to, msg.channel, msg.from).await?;
+                }
+                for node_id in std::mem::take(&mut self.trace_requests) {
+                    let mut sysops = self.traces.get(&node_id).unwrap_or_default();
+                    sysops.retain(|(sysop, _)| *sysop != msg.from);
+                    sysops.push((msg.from, msg.channel));
+                    self.traces.insert(node_id, sysops);
+                    handler.send_traceroute(node_id).await?;
+                }
+            }
+            Status::Traceroute(traceroute) => {
+                if let Some(sysops) = self.traces.get(&traceroute.to) {
+                    self.traces.invalidate(&traceroute.to);
+                    let route = {
+                        let state = handler.state.read().await;
+                        traceroute.describe(|id| state.node_label(id))
+                    };
+                    for (sysop, channel) in sysops {
+                        handler.send_text(route.clone(), sysop, channel).await?;
+                    }
+                }
+            }
+            Status::NodeHeard(node_id, channel) => {
+                if let Some(text) = self.notification_for(node_id).await? {
+                    handler.send_text(text, node_id, channel).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Answers direct messages and delivers subscription notifications
+    /// until the handler is cancelled or Ctrl+C is pressed.
+    pub async fn serve(&mut self, handler: &mut Handler) -> Result<()> {
+        loop {
+            tokio::select! {
+                status = handler.status_rx.recv() => {
+                    let Some(status) = status else { bail!("Channel closed"); };
+                    if let Err(err) = self.handle_status(handler, status).await {
+                        log::error!("Error serving the BBS: {}", err);
+                    }
+                }
+                _ = handler.cancel.cancelled() => break,
+                _ = signal::ctrl_c() => break,
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,7 +1,9 @@
 use std::{collections::HashMap, sync::Mutex};
 
-use crate::bbs::storage::{Channel, ChannelId, ChannelMessage, MessageId, Storage, User, UserId};
-use anyhow::Result;
+use crate::bbs::storage::{
+    Channel, ChannelId, ChannelMessage, MessageId, Storage, Subscription, User, UserId,
+};
+use anyhow::{Result, anyhow};
 
 pub struct InMemoryStorage {
     inner: Mutex<Inner>,
@@ -15,6 +17,7 @@ struct Inner {
     messages: HashMap<ChannelId, Vec<(MessageId, ChannelMessage)>>,
     users: HashMap<UserId, User>,
     users_by_pk: HashMap<[u8; 32], UserId>,
+    subscriptions: HashMap<UserId, HashMap<ChannelId, Subscription>>,
 }
 
 impl InMemoryStorage {
@@ -28,6 +31,7 @@ impl InMemoryStorage {
                 messages: HashMap::new(),
                 users: HashMap::new(),
                 users_by_pk: HashMap::new(),
+                subscriptions: HashMap::new(),
             }),
         }
     }
@@ -56,28 +60,30 @@ impl Storage for InMemoryStorage {
 
     async fn get_channel_by_name(&self, name: &str) -> Result<Channel> {
         let i = self.inner.lock().unwrap();
-        Ok(i.channels
+        i.channels
             .values()
             .find(|c| c.name == name)
             .cloned()
-            .unwrap())
+            .ok_or_else(|| anyhow!("Channel not found"))
     }
 
     async fn rm_channel(&self, cid: ChannelId) -> Result<ChannelId> {
         let mut i = self.inner.lock().unwrap();
         i.channels.remove(&cid);
         i.messages.remove(&cid);
+        for subs in i.subscriptions.values_mut() {
+            subs.remove(&cid);
+        }
         Ok(cid)
     }
 
-    async fn add_message(&self, channel: ChannelId, message: &ChannelMessage) -> Result<u32> {
+    async fn add_message(&self, channel: ChannelId, message: &ChannelMessage) -> Result<MessageId> {
         let mut i = self.inner.lock().unwrap();
         let mid = i.next_mid;
         i.next_mid += 1;
-        i.messages
-            .entry(channel)
-            .or_default()
-            .push((mid, message.clone()));
+        let mut m = message.clone();
+        m.mid = mid;
+        i.messages.entry(channel).or_default().push((mid, m));
         Ok(mid)
     }
 
@@ -124,9 +130,50 @@ impl Storage for InMemoryStorage {
 
     async fn get_user_by_pkhash(&self, pkhash: &[u8; 32]) -> Result<User> {
         let i = self.inner.lock().unwrap();
-        let uid = *i.users_by_pk.get(pkhash).unwrap();
+        let uid = *i
+            .users_by_pk
+            .get(pkhash)
+            .ok_or_else(|| anyhow!("User not found"))?;
         Ok(i.users.get(&uid).cloned().unwrap())
     }
+
+    async fn get_user_by_radio_userid(&self, radio_userid: u32) -> Result<User> {
+        let i = self.inner.lock().unwrap();
+        i.users
+            .values()
+            .find(|u| u.radio_userid == radio_userid)
+            .cloned()
+            .ok_or_else(|| anyhow!("User not found"))
+    }
+
+    async fn add_subscription(&self, subscription: &Subscription) -> Result<()> {
+        let mut i = self.inner.lock().unwrap();
+        i.subscriptions
+            .entry(subscription.uid)
+            .or_default()
+            .insert(subscription.cid, subscription.clone());
+        Ok(())
+    }
+
+    async fn update_subscription(&self, subscription: &Subscription) -> Result<()> {
+        self.add_subscription(subscription).await
+    }
+
+    async fn rm_subscription(&self, uid: UserId, cid: ChannelId) -> Result<()> {
+        let mut i = self.inner.lock().unwrap();
+        if let Some(subs) = i.subscriptions.get_mut(&uid) {
+            subs.remove(&cid);
+        }
+        Ok(())
+    }
+
+    async fn get_subscriptions(&self, uid: UserId) -> Result<Vec<Subscription>> {
+        let i = self.inner.lock().unwrap();
+        Ok(i.subscriptions
+            .get(&uid)
+            .map(|subs| subs.values().cloned().collect())
+            .unwrap_or_default())
+    }
 }
 
 #[tokio::test]
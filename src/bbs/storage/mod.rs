@@ -28,11 +28,23 @@ pub struct Channel {
 
 #[derive(Debug, Clone)]
 pub struct ChannelMessage {
+    // Message Id, increasing in posting order
+    pub mid: MessageId,
     pub ts: u64,
     pub uid: UserId,
     pub text: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub uid: UserId,
+    pub cid: ChannelId,
+    // Timestamp of the last message the user was notified about
+    pub last_ts: u64,
+    // Id of that message, more can be posted in the same second
+    pub last_mid: MessageId,
+}
+
 #[async_trait::async_trait]
 pub trait Storage {
     async fn add_channel(&self, name: &str) -> Result<ChannelId>;
@@ -40,7 +52,7 @@ pub trait Storage {
     async fn get_channel_by_name(&self, name: &str) -> Result<Channel>;
     async fn rm_channel(&self, cid: ChannelId) -> Result<ChannelId>;
 
-    async fn add_message(&self, channel: ChannelId, message: &ChannelMessage) -> Result<u32>;
+    async fn add_message(&self, channel: ChannelId, message: &ChannelMessage) -> Result<MessageId>;
     async fn get_messages(
         &self,
         channel: ChannelId,
@@ -52,6 +64,12 @@ pub trait Storage {
     async fn update_user(&self, user: &User) -> Result<UserId>;
     async fn get_user_by_id(&self, uid: UserId) -> Result<User>;
     async fn get_user_by_pkhash(&self, pkhash: &[u8; 32]) -> Result<User>;
+    async fn get_user_by_radio_userid(&self, radio_userid: u32) -> Result<User>;
+
+    async fn add_subscription(&self, subscription: &Subscription) -> Result<()>;
+    async fn update_subscription(&self, subscription: &Subscription) -> Result<()>;
+    async fn rm_subscription(&self, uid: UserId, cid: ChannelId) -> Result<()>;
+    async fn get_subscriptions(&self, uid: UserId) -> Result<Vec<Subscription>>;
 }
 
 pub async fn test_channels<S: Storage + Send + Sync>(s: &S) -> Result<()> {
@@ -144,6 +162,10 @@ pub async fn test_users<S: Storage + Send + Sync>(s: &S) -> Result<()> {
     assert_eq!(r1_after_pk.uid, id1);
     assert_eq!(r1_after_pk.last_ts, 999);
 
+    let r1_radio = s.get_user_by_radio_userid(11).await?;
+    assert_eq!(r1_radio.uid, id1);
+    assert!(s.get_user_by_radio_userid(12345).await.is_err());
+
     Ok(())
 }
 
@@ -160,21 +182,25 @@ pub async fn test_messages<S: Storage + Send + Sync>(s: &S) -> Result<()> {
     let uid = s.add_user(&u).await?;
 
     let m1 = ChannelMessage {
+        mid: 0,
         ts: 10,
         uid,
         text: "hello".into(),
     };
     let m2 = ChannelMessage {
+        mid: 0,
         ts: 20,
         uid,
         text: "world".into(),
     };
     let m3 = ChannelMessage {
+        mid: 0,
         ts: 30,
         uid,
         text: "bye".into(),
     };
     let m4 = ChannelMessage {
+        mid: 0,
         ts: 40,
         uid,
         text: "other-channel".into(),
@@ -192,6 +218,7 @@ pub async fn test_messages<S: Storage + Send + Sync>(s: &S) -> Result<()> {
     assert_eq!(all_c1[0].text, "hello");
     assert_eq!(all_c1[1].text, "world");
     assert_eq!(all_c1[2].text, "bye");
+    assert_eq!(all_c1[2].mid, id3);
 
     let all_c2 = s.get_messages(cid2, 0, u32::MAX).await?;
     assert_eq!(all_c2.len(), 1);
@@ -222,9 +249,65 @@ pub async fn test_messages<S: Storage + Send + Sync>(s: &S) -> Result<()> {
     Ok(())
 }
 
+pub async fn test_subscriptions<S: Storage + Send + Sync>(s: &S) -> Result<()> {
+    let cid1 = s.add_channel("subs-1").await?;
+    let cid2 = s.add_channel("subs-2").await?;
+
+    let u = User {
+        uid: 0,
+        radio_userid: 2,
+        pk_hash: [4u8; 32],
+        last_ts: 0,
+    };
+    let uid = s.add_user(&u).await?;
+
+    assert!(s.get_subscriptions(uid).await?.is_empty());
+
+    s.add_subscription(&Subscription {
+        uid,
+        cid: cid1,
+        last_ts: 10,
+        last_mid: 0,
+    })
+    .await?;
+    s.add_subscription(&Subscription {
+        uid,
+        cid: cid2,
+        last_ts: 20,
+        last_mid: 0,
+    })
+    .await?;
+
+    let subs = s.get_subscriptions(uid).await?;
+    assert_eq!(subs.len(), 2);
+    assert!(subs.iter().any(|sub| sub.cid == cid1 && sub.last_ts == 10));
+    assert!(subs.iter().any(|sub| sub.cid == cid2 && sub.last_ts == 20));
+
+    s.update_subscription(&Subscription {
+        uid,
+        cid: cid1,
+        last_ts: 99,
+        last_mid: 0,
+    })
+    .await?;
+    let subs = s.get_subscriptions(uid).await?;
+    assert!(subs.iter().any(|sub| sub.cid == cid1 && sub.last_ts == 99));
+
+    s.rm_subscription(uid, cid2).await?;
+    let subs = s.get_subscriptions(uid).await?;
+    assert_eq!(subs.len(), 1);
+    assert_eq!(subs[0].cid, cid1);
+
+    let _ = s.rm_channel(cid1).await?;
+    assert!(s.get_subscriptions(uid).await?.is_empty());
+
+    Ok(())
+}
+
 pub async fn test_storage<S: Storage + Send + Sync>(s: &S) -> Result<()> {
     test_channels(s).await?;
     test_users(s).await?;
     test_messages(s).await?;
+    test_subscriptions(s).await?;
     Ok(())
 }
@@ -0,0 +1,403 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use chrono::{Local, TimeZone};
+use meshtastic::protobufs::{FromRadio, MeshPacket, from_radio};
+use serde::{Deserialize, Serialize};
+use serde_cbor::Deserializer;
+
+/// Version of the capture records written by this build. Version 0 is used
+/// for the bare `MeshPacket` values of the older captures.
+pub const CAPTURE_VERSION: u32 = 1;
+
+const PREFIX: &str = "network.";
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    // Directory of the capture files
+    pub dir: PathBuf,
+    // Size that closes the current file
+    pub max_file_size: u64,
+    // Age that closes the current file
+    pub rotate_interval: Duration,
+    // Size of all the capture files, the oldest are deleted past it
+    pub max_total_size: u64,
+    // Compress the closed files with zstd
+    pub compress: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("."),
+            max_file_size: 16 * 1024 * 1024,
+            rotate_interval: Duration::from_secs(24 * 3600),
+            max_total_size: 512 * 1024 * 1024,
+            compress: true,
+        }
+    }
+}
+
+impl CaptureConfig {
+    /// Reads `CAPTURE_DIR`, `CAPTURE_MAX_FILE_BYTES`, `CAPTURE_ROTATE_SECS`,
+    /// `CAPTURE_MAX_TOTAL_BYTES` and `CAPTURE_COMPRESS`, using the defaults
+    /// for the missing ones.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(dir) = std::env::var("CAPTURE_DIR") {
+            config.dir = PathBuf::from(dir);
+        }
+        if let Some(bytes) = std::env::var("CAPTURE_MAX_FILE_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.max_file_size = bytes;
+        }
+        if let Some(secs) = std::env::var("CAPTURE_ROTATE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.rotate_interval = Duration::from_secs(secs);
+        }
+        if let Some(bytes) = std::env::var("CAPTURE_MAX_TOTAL_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.max_total_size = bytes;
+        }
+        if let Some(compress) = std::env::var("CAPTURE_COMPRESS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.compress = compress;
+        }
+        config
+    }
+}
+
+struct OpenCapture {
+    file: File,
+    path: PathBuf,
+    opened: Instant,
+    size: u64,
+}
+
+/// Appends records to `network.<time>.cbor` files in the capture directory,
+/// starting a new file when the current one is too big or too old. Closed
+/// files are compressed to `.cbor.zst` and the oldest files are deleted to
+/// keep the directory within its budget.
+pub struct CaptureWriter {
+    config: CaptureConfig,
+    current: Option<OpenCapture>,
+}
+
+impl CaptureWriter {
+    /// Closes the files left open by a previous run.
+    pub fn new(config: CaptureConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let writer = Self {
+            config,
+            current: None,
+        };
+        for path in writer.files()? {
+            if path.extension().is_some_and(|ext| ext == "cbor") {
+                writer.close(&path)?;
+            }
+        }
+        writer.enforce_budget()?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> Result<()> {
+        let mut bytes = Vec::new();
+        record.write(&mut bytes)?;
+
+        if self.current.as_ref().is_some_and(|current| {
+            current.size + bytes.len() as u64 > self.config.max_file_size
+                || current.opened.elapsed() >= self.config.rotate_interval
+        }) {
+            self.rotate()?;
+        }
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => self.open()?,
+        };
+        let current = self.current.insert(current);
+        current.file.write_all(&bytes)?;
+        current.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Closes the current file, the next record starts a new one.
+    pub fn rotate(&mut self) -> Result<()> {
+        if let Some(current) = self.current.take() {
+            drop(current.file);
+            self.close(&current.path)?;
+            self.enforce_budget()?;
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> Result<OpenCapture> {
+        let stem = format!("{PREFIX}{}", Local::now().format("%Y-%m-%dT%H%M%S"));
+        let mut path = self.config.dir.join(format!("{stem}.cbor"));
+        let mut n = 0;
+        while path.exists() || path.with_extension("cbor.zst").exists() {
+            n += 1;
+            path = self.config.dir.join(format!("{stem}_{n:03}.cbor"));
+        }
+        let file = File::options().create(true).append(true).open(&path)?;
+        Ok(OpenCapture {
+            file,
+            path,
+            opened: Instant::now(),
+            size: 0,
+        })
+    }
+
+    fn close(&self, path: &Path) -> Result<()> {
+        if !self.config.compress {
+            return Ok(());
+        }
+        let compressed = path.with_extension("cbor.zst");
+        let mut encoder = zstd::Encoder::new(File::create(&compressed)?, ZSTD_LEVEL)?;
+        std::io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    /// Capture files of the directory, oldest first.
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.config.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| {
+                        name.starts_with(PREFIX)
+                            && (name.ends_with(".cbor") || name.ends_with(".cbor.zst"))
+                    })
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    fn enforce_budget(&self) -> Result<()> {
+        let files = self.files()?;
+        let mut sizes = Vec::new();
+        for path in &files {
+            sizes.push(fs::metadata(path)?.len());
+        }
+        let mut total: u64 = sizes.iter().sum();
+        for (path, size) in files.iter().zip(sizes) {
+            if total <= self.config.max_total_size {
+                break;
+            }
+            if self.current.as_ref().is_some_and(|c| &c.path == path) {
+                continue;
+            }
+            log::info!("Deleting capture {}", path.display());
+            fs::remove_file(path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+/// Everything the radio sent, as received by us.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    pub version: u32,
+    // Local receive time, milliseconds since the Unix epoch
+    pub ts: i64,
+    // Transport of the radio, e.g. `ble://Meshtastic_1234`
+    pub source: String,
+    pub from_radio: FromRadio,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRecord {
+    Record(CaptureRecord),
+    Legacy(MeshPacket),
+}
+
+impl CaptureRecord {
+    pub fn new(source: &str, from_radio: FromRadio) -> Self {
+        Self {
+            version: CAPTURE_VERSION,
+            ts: Local::now().timestamp_millis(),
+            source: source.to_string(),
+            from_radio,
+        }
+    }
+
+    /// Record of a bare `MeshPacket`, timestamped with its `rx_time`.
+    fn legacy(mesh_packet: MeshPacket) -> Self {
+        Self {
+            version: 0,
+            ts: mesh_packet.rx_time as i64 * 1000,
+            source: String::new(),
+            from_radio: FromRadio {
+                payload_variant: Some(from_radio::PayloadVariant::Packet(mesh_packet)),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn mesh_packet(&self) -> Option<&MeshPacket> {
+        match &self.from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::Packet(mesh_packet)) => Some(mesh_packet),
+            _ => None,
+        }
+    }
+
+    /// Local receive time as `YYYY-MM-DD HH:MM:SS.mmm`, `-` when unknown.
+    pub fn time(&self) -> String {
+        match Local.timestamp_millis_opt(self.ts).single() {
+            Some(time) if self.ts > 0 => time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            _ => "-".to_string(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        serde_cbor::to_writer(writer, self)?;
+        Ok(())
+    }
+}
+
+/// Records of a capture, reading both the enveloped and the legacy format.
+pub fn records<R: Read>(reader: R) -> impl Iterator<Item = Result<CaptureRecord>> {
+    Deserializer::from_reader(reader)
+        .into_iter::<StoredRecord>()
+        .map(|stored| match stored? {
+            StoredRecord::Record(record) if record.version > CAPTURE_VERSION => {
+                bail!("Unsupported capture version {}", record.version)
+            }
+            StoredRecord::Record(record) => Ok(record),
+            StoredRecord::Legacy(mesh_packet) => Ok(CaptureRecord::legacy(mesh_packet)),
+        })
+}
+
+/// Records of a capture file, decompressing `.zst` files.
+pub fn open(path: &Path) -> Result<impl Iterator<Item = Result<CaptureRecord>>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "zst") {
+        Box::new(zstd::Decoder::new(file)?)
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(records(reader))
+}
+
+#[test]
+fn test_read_legacy_and_records() -> Result<()> {
+    let mut capture = Vec::new();
+    let legacy = MeshPacket {
+        id: 1,
+        rx_time: 1700000000,
+        ..Default::default()
+    };
+    serde_cbor::to_writer(&mut capture, &legacy)?;
+    let record = CaptureRecord::new(
+        "tcp://localhost:4403",
+        FromRadio {
+            payload_variant: Some(from_radio::PayloadVariant::ConfigCompleteId(7)),
+            ..Default::default()
+        },
+    );
+    record.write(&mut capture)?;
+    let future = CaptureRecord {
+        version: CAPTURE_VERSION + 1,
+        ..record.clone()
+    };
+    future.write(&mut capture)?;
+
+    let records: Vec<_> = records(capture.as_slice()).collect();
+    assert_eq!(records.len(), 3);
+
+    let first = records[0].as_ref().unwrap();
+    assert_eq!(first.version, 0);
+    assert_eq!(first.ts, 1700000000 * 1000);
+    assert_eq!(first.mesh_packet().map(|p| p.id), Some(1));
+
+    let second = records[1].as_ref().unwrap();
+    assert_eq!(second.version, CAPTURE_VERSION);
+    assert_eq!(second.source, "tcp://localhost:4403");
+    assert_eq!(second.from_radio, record.from_radio);
+    assert!(second.mesh_packet().is_none());
+
+    assert!(records[2].is_err());
+    Ok(())
+}
+
+#[test]
+fn test_rotation_and_budget() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("mbbs-captures-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let record = CaptureRecord::new(
+        "tcp://localhost:4403",
+        FromRadio {
+            payload_variant: Some(from_radio::PayloadVariant::Packet(MeshPacket {
+                id: 1,
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+    );
+    let mut record_size = Vec::new();
+    record.write(&mut record_size)?;
+    let record_size = record_size.len() as u64;
+
+    // Two records per file
+    let mut writer = CaptureWriter::new(CaptureConfig {
+        dir: dir.clone(),
+        max_file_size: record_size * 2,
+        max_total_size: u64::MAX,
+        ..Default::default()
+    })?;
+    for _ in 0..5 {
+        writer.write(&record)?;
+    }
+    let files = writer.files()?;
+    assert_eq!(files.len(), 3);
+    assert!(
+        files[..2]
+            .iter()
+            .all(|f| f.to_string_lossy().ends_with(".cbor.zst"))
+    );
+    assert!(files[2].to_string_lossy().ends_with(".cbor"));
+    assert_eq!(open(&files[0])?.count(), 2);
+    assert_eq!(open(&files[2])?.count(), 1);
+
+    // A new writer closes the last file and deletes the oldest over budget
+    let compressed = fs::metadata(&files[1])?.len();
+    drop(writer);
+    let writer = CaptureWriter::new(CaptureConfig {
+        dir: dir.clone(),
+        max_total_size: compressed * 2,
+        ..Default::default()
+    })?;
+    let files = writer.files()?;
+    assert_eq!(files.len(), 2);
+    assert!(
+        files
+            .iter()
+            .all(|f| f.to_string_lossy().ends_with(".cbor.zst"))
+    );
+    assert_eq!(
+        open(&files[1])?.next().unwrap()?.source,
+        "tcp://localhost:4403"
+    );
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
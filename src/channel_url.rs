@@ -0,0 +1,285 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use clap::{Subcommand, ValueEnum};
+use meshtastic::protobufs::{
+    ChannelSet, ChannelSettings, ModuleSettings,
+    config::{
+        LoRaConfig,
+        lo_ra_config::{ModemPreset, RegionCode},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::mesh::channel_url::{self, channel_name, preset_name};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ChannelUrlCommand {
+    /// Print the channels, PSKs and modem settings of a `https://meshtastic.org/e/#...` URL
+    Decode {
+        url: String,
+        /// `json` prints a channel config that `encode` reads back
+        #[arg(long, value_enum, default_value = "text")]
+        format: Format,
+    },
+    /// Print the channel URL, the text of its QR code, of a JSON channel config
+    Encode {
+        config: PathBuf,
+        /// Add the channels to the ones of the radio instead of replacing them
+        #[arg(long)]
+        add: bool,
+    },
+}
+
+/// Channel of a channel config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ChannelConfig {
+    // Empty for a primary channel named after the modem preset
+    #[serde(default)]
+    name: String,
+    // Base64, `AQ==` is the default key and an empty one disables the encryption
+    psk: String,
+    #[serde(default)]
+    uplink: bool,
+    #[serde(default)]
+    downlink: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    position_precision: Option<u32>,
+}
+
+/// Channels and modem settings, as in a channel URL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ChannelsConfig {
+    channels: Vec<ChannelConfig>,
+    // Modem preset, `LongFast` or `LONG_FAST`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+    // Region code, such as `EU_868`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hop_limit: Option<u32>,
+}
+
+impl From<&ChannelSet> for ChannelsConfig {
+    fn from(channel_set: &ChannelSet) -> Self {
+        let lora = channel_set.lora_config.as_ref();
+        Self {
+            channels: channel_set
+                .settings
+                .iter()
+                .map(|settings| ChannelConfig {
+                    name: settings.name.clone(),
+                    psk: BASE64.encode(&settings.psk),
+                    uplink: settings.uplink_enabled,
+                    downlink: settings.downlink_enabled,
+                    position_precision: settings
+                        .module_settings
+                        .as_ref()
+                        .map(|module| module.position_precision),
+                })
+                .collect(),
+            preset: lora
+                .filter(|lora| lora.use_preset)
+                .map(|lora| preset_name(lora.modem_preset)),
+            region: lora
+                .and_then(|lora| RegionCode::try_from(lora.region).ok())
+                .filter(|region| *region != RegionCode::Unset)
+                .map(|region| region.as_str_name().to_string()),
+            hop_limit: lora.map(|lora| lora.hop_limit).filter(|hops| *hops > 0),
+        }
+    }
+}
+
+impl ChannelsConfig {
+    fn to_channel_set(&self) -> Result<ChannelSet> {
+        let settings = self
+            .channels
+            .iter()
+            .map(|channel| {
+                Ok(ChannelSettings {
+                    name: channel.name.clone(),
+                    psk: BASE64.decode(&channel.psk)?,
+                    uplink_enabled: channel.uplink,
+                    downlink_enabled: channel.downlink,
+                    module_settings: channel.position_precision.map(|position_precision| {
+                        ModuleSettings {
+                            position_precision,
+                            ..Default::default()
+                        }
+                    }),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<_>>()?;
+        let mut lora = LoRaConfig {
+            use_preset: true,
+            hop_limit: self.hop_limit.unwrap_or(3),
+            tx_enabled: true,
+            ..Default::default()
+        };
+        if let Some(preset) = &self.preset {
+            lora.modem_preset = parse_preset(preset)? as i32;
+        }
+        if let Some(region) = &self.region {
+            lora.region = RegionCode::from_str_name(&region.to_uppercase())
+                .ok_or_else(|| anyhow!("Unknown region '{region}'"))?
+                as i32;
+        }
+        Ok(ChannelSet {
+            settings,
+            lora_config: Some(lora),
+        })
+    }
+}
+
+/// Modem preset from its display name, `LongFast`, or its `LONG_FAST` name.
+fn parse_preset(preset: &str) -> Result<ModemPreset> {
+    let camel_case = preset.chars().any(|c| c.is_ascii_lowercase());
+    let mut name = String::new();
+    for (i, c) in preset.chars().enumerate() {
+        if i > 0 && camel_case && c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    ModemPreset::from_str_name(&name).ok_or_else(|| anyhow!("Unknown modem preset '{preset}'"))
+}
+
+fn describe_psk(psk: &[u8]) -> String {
+    match psk {
+        [] | [0] => "no encryption".to_string(),
+        [1] => "default key".to_string(),
+        [n] => format!("well-known key {n}"),
+        psk if psk.len() <= 16 => "AES-128".to_string(),
+        _ => "AES-256".to_string(),
+    }
+}
+
+fn describe(channel_set: &ChannelSet) -> String {
+    let mut text = String::new();
+    for (index, settings) in channel_set.settings.iter().enumerate() {
+        let mut flags = vec![describe_psk(&settings.psk)];
+        if settings.uplink_enabled {
+            flags.push("uplink".to_string());
+        }
+        if settings.downlink_enabled {
+            flags.push("downlink".to_string());
+        }
+        if let Some(module) = &settings.module_settings
+            && module.position_precision > 0
+        {
+            flags.push(format!("position precision {}", module.position_precision));
+        }
+        text.push_str(&format!(
+            "{} {} psk {} ({})\n",
+            index,
+            channel_name(channel_set, settings),
+            BASE64.encode(&settings.psk),
+            flags.join(", ")
+        ));
+    }
+    if let Some(lora) = &channel_set.lora_config {
+        let modem = if lora.use_preset {
+            preset_name(lora.modem_preset)
+        } else {
+            format!(
+                "custom bw {}kHz sf {} cr 4/{}",
+                lora.bandwidth, lora.spread_factor, lora.coding_rate
+            )
+        };
+        let region = RegionCode::try_from(lora.region)
+            .map(|region| region.as_str_name())
+            .unwrap_or("?");
+        text.push_str(&format!(
+            "LoRa {modem}, region {region}, hop limit {}\n",
+            lora.hop_limit
+        ));
+    }
+    // Ready to paste in the `.env` to decrypt the packets of these channels
+    let keys: Vec<String> = channel_set
+        .settings
+        .iter()
+        .map(|settings| {
+            format!(
+                "{}:{}",
+                channel_name(channel_set, settings),
+                BASE64.encode(&settings.psk)
+            )
+        })
+        .collect();
+    text.push_str(&format!("CHANNEL_KEYS={}\n", keys.join(",")));
+    text
+}
+
+pub fn channel_url(command: ChannelUrlCommand) -> Result<()> {
+    match command {
+        ChannelUrlCommand::Decode { url, format } => {
+            let channel_set = channel_url::decode(&url)?;
+            match format {
+                Format::Text => print!("{}", describe(&channel_set)),
+                Format::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&ChannelsConfig::from(&channel_set))?
+                ),
+            }
+        }
+        ChannelUrlCommand::Encode { config, add } => {
+            let config: ChannelsConfig = serde_json::from_slice(&std::fs::read(config)?)?;
+            println!("{}", channel_url::encode(&config.to_channel_set()?, add));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_config_round_trip() -> Result<()> {
+    let config: ChannelsConfig = serde_json::from_str(
+        r#"{
+            "channels": [
+                {"psk": "AQ==", "position_precision": 13},
+                {"name": "Private", "psk": "q83vEjRWeJCrze8SNFZ4kA==", "uplink": true}
+            ],
+            "preset": "MediumFast",
+            "region": "eu_868"
+        }"#,
+    )?;
+    assert_eq!(parse_preset("LONG_FAST")?, ModemPreset::LongFast);
+    assert!(parse_preset("Fastest").is_err());
+
+    let channel_set = config.to_channel_set()?;
+    let url = channel_url::encode(&channel_set, false);
+    let decoded = channel_url::decode(&url)?;
+    assert_eq!(decoded, channel_set);
+    let mut expected = config.clone();
+    expected.region = Some("EU_868".into());
+    expected.hop_limit = Some(3);
+    assert_eq!(ChannelsConfig::from(&decoded), expected);
+
+    let text = describe(&decoded);
+    let mut lines = text.lines();
+    assert_eq!(
+        lines.next(),
+        Some("0 MediumFast psk AQ== (default key, position precision 13)")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("1 Private psk q83vEjRWeJCrze8SNFZ4kA== (AES-128, uplink)")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("LoRa MediumFast, region EU_868, hop limit 3")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("CHANNEL_KEYS=MediumFast:AQ==,Private:q83vEjRWeJCrze8SNFZ4kA==")
+    );
+    Ok(())
+}
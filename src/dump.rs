@@ -0,0 +1,296 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow, bail};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use clap::{Args, ValueEnum};
+use meshtastic::{
+    Message,
+    protobufs::{
+        Data, MeshPacket, PortNum, Position, Routing, Telemetry, User, from_radio, mesh_packet,
+    },
+};
+use serde_json::{Value, json};
+
+use crate::capture::{self, CaptureRecord};
+use crate::mesh::crypto::{ChannelKey, ChannelKeys};
+use crate::utils::{csv_field, node_id, parse_node_id, portnum_name};
+
+const CSV_HEADER: &str =
+    "time,ts,source,id,from,to,channel,portnum,hop_limit,hop_start,rx_snr,rx_rssi,payload";
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// One line per record, human readable
+    Text,
+    /// One JSON object per packet
+    Jsonl,
+    /// One row per packet with a header
+    Csv,
+}
+
+#[derive(Debug, Args)]
+pub struct DumpArgs {
+    /// Paths to the CBOR files, `.cbor.zst` files are decompressed
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Only packets sent by this node, `!aabbccdd` or its number
+    #[arg(long, value_parser = parse_node_id)]
+    from: Option<u32>,
+    /// Only packets sent to this node, `!aabbccdd` or its number
+    #[arg(long, value_parser = parse_node_id)]
+    to: Option<u32>,
+    /// Only packets of this application, e.g. `TEXT_MESSAGE_APP` or its number
+    #[arg(long, value_parser = parse_portnum)]
+    portnum: Option<i32>,
+    /// Only packets on this channel index
+    #[arg(long)]
+    channel: Option<u32>,
+    /// Only records received at or after this local time, `YYYY-MM-DD[ HH:MM[:SS]]`
+    #[arg(long, value_parser = parse_time)]
+    since: Option<DateTime<Local>>,
+    /// Only records received before this local time, `YYYY-MM-DD[ HH:MM[:SS]]`
+    #[arg(long, value_parser = parse_time)]
+    until: Option<DateTime<Local>>,
+    /// Channel key to decrypt packets with, `<name>:<base64 psk>`, besides the
+    /// default one and those of `CHANNEL_KEYS`
+    #[arg(long = "key")]
+    keys: Vec<ChannelKey>,
+    #[arg(long, value_enum, default_value = "text")]
+    format: Format,
+}
+
+fn parse_portnum(s: &str) -> Result<i32> {
+    if let Ok(portnum) = s.parse() {
+        return Ok(portnum);
+    }
+    PortNum::from_str_name(&s.to_uppercase())
+        .map(|portnum| portnum as i32)
+        .ok_or_else(|| anyhow!("Unknown portnum '{s}'"))
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Local>> {
+    let naive = if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0).unwrap()
+    } else if let Ok(time) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S") {
+        time
+    } else {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")?
+    };
+    match Local.from_local_datetime(&naive).earliest() {
+        Some(time) => Ok(time),
+        None => bail!("Invalid local time '{s}'"),
+    }
+}
+
+/// Payload of a packet as JSON, decoding the applications we know about.
+pub fn decode_payload(data: &Data) -> Value {
+    let decoded = match PortNum::try_from(data.portnum) {
+        Ok(PortNum::TextMessageApp) => return json!(String::from_utf8_lossy(&data.payload)),
+        Ok(PortNum::PositionApp) => Position::decode(data.payload.as_slice())
+            .ok()
+            .and_then(|v| serde_json::to_value(v).ok()),
+        Ok(PortNum::TelemetryApp) => Telemetry::decode(data.payload.as_slice())
+            .ok()
+            .and_then(|v| serde_json::to_value(v).ok()),
+        Ok(PortNum::NodeinfoApp) => User::decode(data.payload.as_slice())
+            .ok()
+            .and_then(|v| serde_json::to_value(v).ok()),
+        Ok(PortNum::RoutingApp) => Routing::decode(data.payload.as_slice())
+            .ok()
+            .and_then(|v| serde_json::to_value(v).ok()),
+        _ => None,
+    };
+    decoded.unwrap_or_else(|| {
+        json!(
+            data.payload
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        )
+    })
+}
+
+impl DumpArgs {
+    fn matches_time(&self, record: &CaptureRecord) -> bool {
+        self.since
+            .is_none_or(|since| record.ts >= since.timestamp_millis())
+            && self
+                .until
+                .is_none_or(|until| record.ts < until.timestamp_millis())
+    }
+
+    fn filters_packets(&self) -> bool {
+        self.from.is_some() || self.to.is_some() || self.portnum.is_some() || self.channel.is_some()
+    }
+
+    fn matches_packet(&self, mesh_packet: &MeshPacket) -> bool {
+        let portnum = match &mesh_packet.payload_variant {
+            Some(mesh_packet::PayloadVariant::Decoded(data)) => Some(data.portnum),
+            _ => None,
+        };
+        self.from.is_none_or(|from| mesh_packet.from == from)
+            && self.to.is_none_or(|to| mesh_packet.to == to)
+            && self
+                .channel
+                .is_none_or(|channel| mesh_packet.channel == channel)
+            && self.portnum.is_none_or(|p| portnum == Some(p))
+    }
+}
+
+fn packet_json(record: &CaptureRecord, mesh_packet: &MeshPacket) -> Value {
+    let (portnum, payload) = match &mesh_packet.payload_variant {
+        Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+            (portnum_name(data.portnum), decode_payload(data))
+        }
+        Some(mesh_packet::PayloadVariant::Encrypted(_)) => ("ENCRYPTED".into(), Value::Null),
+        None => (String::new(), Value::Null),
+    };
+    json!({
+        "time": record.time(),
+        "ts": record.ts,
+        "source": record.source,
+        "id": mesh_packet.id,
+        "from": node_id(mesh_packet.from),
+        "to": node_id(mesh_packet.to),
+        "channel": mesh_packet.channel,
+        "portnum": portnum,
+        "hop_limit": mesh_packet.hop_limit,
+        "hop_start": mesh_packet.hop_start,
+        "rx_snr": mesh_packet.rx_snr,
+        "rx_rssi": mesh_packet.rx_rssi,
+        "payload": payload,
+    })
+}
+
+fn csv_row(packet: &Value) -> String {
+    CSV_HEADER
+        .split(',')
+        .map(|column| csv_field(&packet[column]))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn text_line(record: &CaptureRecord) -> String {
+    match record.mesh_packet() {
+        Some(mesh_packet) => {
+            let packet = packet_json(record, mesh_packet);
+            format!(
+                "{} {} {} → {} ch{} {} {}",
+                record.time(),
+                record.source,
+                node_id(mesh_packet.from),
+                node_id(mesh_packet.to),
+                mesh_packet.channel,
+                packet["portnum"].as_str().unwrap_or_default(),
+                packet["payload"]
+            )
+        }
+        None => format!(
+            "{} {} {:?}",
+            record.time(),
+            record.source,
+            record.from_radio.payload_variant
+        ),
+    }
+}
+
+/// Prints the records of the capture files that pass the filters. The JSON
+/// Lines and CSV formats only print the mesh packets.
+pub fn dump(args: DumpArgs) -> Result<()> {
+    let mut keys = ChannelKeys::from_env()?;
+    for key in &args.keys {
+        keys.add(key.clone());
+    }
+    if let Format::Csv = args.format {
+        println!("{}", CSV_HEADER);
+    }
+    for path in &args.files {
+        for record in capture::open(path)? {
+            let mut record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    eprintln!("Err({:?})", err);
+                    continue;
+                }
+            };
+            if !args.matches_time(&record) {
+                continue;
+            }
+            if let Some(from_radio::PayloadVariant::Packet(mesh_packet)) =
+                &mut record.from_radio.payload_variant
+            {
+                keys.decrypt_in_place(mesh_packet);
+            }
+            let mesh_packet = record.mesh_packet();
+            match mesh_packet {
+                Some(mesh_packet) if !args.matches_packet(mesh_packet) => continue,
+                None if args.filters_packets() => continue,
+                _ => {}
+            }
+            match (args.format, mesh_packet) {
+                (Format::Text, _) => println!("{}", text_line(&record)),
+                (Format::Jsonl, Some(mesh_packet)) => {
+                    println!("{}", packet_json(&record, mesh_packet))
+                }
+                (Format::Csv, Some(mesh_packet)) => {
+                    println!("{}", csv_row(&packet_json(&record, mesh_packet)))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_filters_and_output() {
+    use meshtastic::protobufs::FromRadio;
+
+    let text = |from, to, text: &str| CaptureRecord {
+        ts: 1_700_000_000_000,
+        ..CaptureRecord::new(
+            "ble://radio",
+            FromRadio {
+                payload_variant: Some(from_radio::PayloadVariant::Packet(MeshPacket {
+                    id: 9,
+                    from,
+                    to,
+                    payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+                        portnum: PortNum::TextMessageApp as i32,
+                        payload: text.as_bytes().to_vec(),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        )
+    };
+    let args = DumpArgs {
+        files: vec![],
+        from: Some(parse_node_id("!000000aa").unwrap()),
+        to: None,
+        portnum: Some(parse_portnum("text_message_app").unwrap()),
+        channel: Some(0),
+        since: Some(parse_time("2023-11-14").unwrap()),
+        until: None,
+        keys: vec![],
+        format: Format::Csv,
+    };
+
+    let hello = text(0xaa, 0xffffffff, "hello, mesh");
+    let packet = hello.mesh_packet().unwrap();
+    assert!(args.matches_time(&hello));
+    assert!(args.matches_packet(packet));
+    assert!(!args.matches_packet(text(0xbb, 0xaa, "hi").mesh_packet().unwrap()));
+
+    let json = packet_json(&hello, packet);
+    assert_eq!(json["from"], "!000000aa");
+    assert_eq!(json["portnum"], "TEXT_MESSAGE_APP");
+    assert_eq!(json["payload"], "hello, mesh");
+    assert!(csv_row(&json).ends_with(",\"hello, mesh\""));
+
+    assert_eq!(parse_node_id("170").unwrap(), 0xaa);
+    assert!(parse_portnum("NOT_AN_APP").is_err());
+    assert!(parse_time("yesterday").is_err());
+}
@@ -1,31 +1,40 @@
 //! This example connects via Bluetooth LE to the radio and prints out all received packets.
 #[allow(unused)]
 use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::fs::File;
-use std::io::BufReader;
 use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use meshtastic::api::StreamApi;
-use meshtastic::protobufs::MeshPacket;
 
+mod admin;
+mod alerts;
 mod bbs;
+mod capture;
+mod channel_url;
+mod dump;
 mod mesh;
+mod nodes;
+mod positions;
 mod repl;
+mod replay;
 mod service;
-mod storage;
+mod stats;
 mod telegram;
+mod topology;
 mod utils;
 
 use meshtastic::utils::generate_rand_id;
 use meshtastic::utils::stream::{BleId, build_ble_stream};
-use serde_cbor::Deserializer;
-use storage::Storage;
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
+use crate::alerts::TelemetryAlerts;
+use crate::capture::{CaptureConfig, CaptureWriter};
+use crate::mesh::crypto::ChannelKeys;
+use crate::mesh::service::{NodeDb, NodeDbConfig};
+use crate::mesh::transport::Transport;
 use crate::service::Service;
 use crate::telegram::TelegramBot;
 
@@ -50,15 +59,55 @@ enum Commands {
     /// Fast check
     FastCheck,
     /// Discover BLE nodes
-    Repl,
+    Repl {
+        /// Radio to connect at startup, `ble://<name>`, `tcp://<host>[:<port>]` or
+        /// `serial://<device>[?baud=<rate>]`
+        #[arg(long)]
+        transport: Option<Transport>,
+    },
     /// Start the network node
-    Start,
+    Start {
+        /// Radio to use, `ble://<name>`, `tcp://<host>[:<port>]` or
+        /// `serial://<device>[?baud=<rate>]`, defaults to the environment configuration
+        #[arg(long)]
+        transport: Option<Transport>,
+    },
     /// Discover peers
     Discover,
-    /// Dump and pretty-print a CBOR file
-    Dump {
-        /// Path to the CBOR file
-        file: PathBuf,
+    /// Dump and pretty-print CBOR capture files
+    Dump(dump::DumpArgs),
+    /// Summarize the traffic of CBOR capture files
+    Stats(stats::StatsArgs),
+    /// Export the node tracks from capture files or the radio as GPX or GeoJSON
+    ExportPositions(positions::ExportArgs),
+    /// List, show and export the nodes of the node database or the radio
+    Nodes {
+        #[command(subcommand)]
+        command: nodes::NodesCommand,
+    },
+    /// Get or set the owner and config of a node, or reboot it, through the radio
+    Admin(admin::AdminArgs),
+    /// Decode channel URLs and generate them from a channel config
+    ChannelUrl {
+        #[command(subcommand)]
+        command: channel_url::ChannelUrlCommand,
+    },
+    /// Graph the links between nodes from capture files or the radio as DOT or JSON
+    Topology(topology::TopologyArgs),
+    /// Feed captured CBOR files back through the packet processing
+    Replay {
+        /// Paths to the CBOR files, replayed in the given order
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Processing that receives the packets, its output is printed to stdout
+        #[arg(long, value_enum, default_value = "telegram")]
+        sink: replay::Sink,
+        /// Wait between packets as long as when they were received
+        #[arg(long)]
+        realtime: bool,
+        /// Node number of the radio that made the capture, guessed when missing
+        #[arg(long)]
+        node: Option<u32>,
     },
 }
 
@@ -73,10 +122,22 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.command {
         Commands::FastCheck => fast_check().await?,
-        Commands::Repl => repl::repl().await?,
-        Commands::Start => start().await?,
+        Commands::Repl { transport } => repl::repl(transport).await?,
+        Commands::Start { transport } => start(transport).await?,
         Commands::Discover => discover().await?,
-        Commands::Dump { file } => dump(file).await?,
+        Commands::Dump(args) => dump::dump(args)?,
+        Commands::Stats(args) => stats::stats(args)?,
+        Commands::ExportPositions(args) => positions::export(args).await?,
+        Commands::Nodes { command } => nodes::nodes(command).await?,
+        Commands::Admin(args) => admin::admin(args).await?,
+        Commands::ChannelUrl { command } => channel_url::channel_url(command)?,
+        Commands::Topology(args) => topology::topology(args).await?,
+        Commands::Replay {
+            files,
+            sink,
+            realtime,
+            node,
+        } => replay::replay(files, sink, realtime, node).await?,
     }
 
     Ok(())
@@ -112,17 +173,17 @@ async fn fast_check() -> Result<()> {
     Ok(())
 }
 
-async fn dump(path: PathBuf) -> Result<()> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let stream = Deserializer::from_reader(reader).into_iter::<MeshPacket>();
-    for mesh_packet in stream {
-        println!("{:?}", mesh_packet);
+async fn discover() -> Result<()> {
+    log::info!("Scanning serial ports...");
+    match mesh::transport::likely_serial_ports() {
+        Ok(ports) => {
+            for port in ports {
+                log::info!("Found serial port: serial://{}", port);
+            }
+        }
+        Err(err) => log::warn!("Unable to list serial ports: {}", err),
     }
-    Ok(())
-}
 
-async fn discover() -> Result<()> {
     log::info!("Scanning BLE devices...");
     let devices = meshtastic::utils::stream::available_ble_devices(Duration::from_secs(5)).await?;
     for device in devices {
@@ -135,12 +196,15 @@ async fn discover() -> Result<()> {
     Ok(())
 }
 
-async fn start() -> Result<()> {
+async fn start(transport: Option<Transport>) -> Result<()> {
     println!("VERSION {}", VERSION);
 
     let telegram_bot_token = std::env::var("TELEGRAM_BOT_TOKEN")?;
     let telegram_bot_chatid = std::env::var("TELEGRAM_BOT_CHATID")?.parse()?;
-    let ble_device = std::env::var("BLE_DEVICE")?;
+    let transport = match transport {
+        Some(transport) => transport,
+        None => Transport::from_env()?,
+    };
 
     let cancel = CancellationToken::new();
     let cancel_ctrl_c = cancel.clone();
@@ -151,10 +215,21 @@ async fn start() -> Result<()> {
 
     log::info!("Connecting to telegram...");
     let mut bot = TelegramBot::new(telegram_bot_token, telegram_bot_chatid);
-    let mut storage = Storage::default();
+    let mut nodes = NodeDb::load(NodeDbConfig::from_env())?;
+    let mut capture = CaptureWriter::new(CaptureConfig::from_env())?;
+    let mut alerts = TelemetryAlerts::from_env()?;
+    let keys = ChannelKeys::from_env()?;
 
     loop {
-        let mut service = Service::new(cancel.clone(), &mut bot, &mut storage, ble_device.clone());
+        let mut service = Service::new(
+            cancel.clone(),
+            &mut bot,
+            &mut nodes,
+            &mut capture,
+            &mut alerts,
+            &keys,
+            transport.clone(),
+        );
         if let Err(err) = service.run().await {
             bot.send_message(format!("⚠️ Error running service: {}", err))
                 .await?;
@@ -166,5 +241,7 @@ async fn start() -> Result<()> {
             _ = tokio::time::sleep(Duration::from_secs(5)) => {},
         };
     }
+    capture.rotate()?;
+    nodes.save()?;
     Ok(())
 }
@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use meshtastic::protobufs::{
+    AdminMessage, Channel, Config, User,
+    admin_message::{self, ConfigType},
+};
+
+// Radios forget a passkey 300s after sending it, the margin covers the
+// time to deliver the request
+const SESSION_TTL: Duration = Duration::from_secs(240);
+
+/// Remote administration request, sent to the `AdminApp` of a node
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminRequest {
+    GetOwner,
+    SetOwner(User),
+    GetConfig(ConfigType),
+    SetConfig(Config),
+    // Index of the channel, 0 being the primary one
+    GetChannel(u32),
+    SetChannel(Channel),
+    // Seconds to wait before rebooting
+    Reboot(i32),
+}
+
+impl AdminRequest {
+    /// Requests answered with an `AdminResponse`, the others change the node
+    /// and need the session passkey of a previous response.
+    pub fn wants_response(&self) -> bool {
+        matches!(
+            self,
+            AdminRequest::GetOwner | AdminRequest::GetConfig(_) | AdminRequest::GetChannel(_)
+        )
+    }
+
+    pub fn to_admin_message(&self, session_passkey: Vec<u8>) -> AdminMessage {
+        use admin_message::PayloadVariant;
+        let payload = match self {
+            AdminRequest::GetOwner => PayloadVariant::GetOwnerRequest(true),
+            AdminRequest::SetOwner(user) => PayloadVariant::SetOwner(user.clone()),
+            AdminRequest::GetConfig(config_type) => {
+                PayloadVariant::GetConfigRequest(*config_type as i32)
+            }
+            AdminRequest::SetConfig(config) => PayloadVariant::SetConfig(config.clone()),
+            // Sent as index + 1, as protobuf does not tell 0 from missing
+            AdminRequest::GetChannel(index) => PayloadVariant::GetChannelRequest(index + 1),
+            AdminRequest::SetChannel(channel) => PayloadVariant::SetChannel(channel.clone()),
+            AdminRequest::Reboot(secs) => PayloadVariant::RebootSeconds(*secs),
+        };
+        AdminMessage {
+            session_passkey,
+            payload_variant: Some(payload),
+        }
+    }
+}
+
+/// Response of a node to an `AdminRequest`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminResponse {
+    Owner(User),
+    Config(Config),
+    Channel(Channel),
+}
+
+impl AdminResponse {
+    pub fn from_admin_message(admin_message: &AdminMessage) -> Option<Self> {
+        match &admin_message.payload_variant {
+            Some(admin_message::PayloadVariant::GetOwnerResponse(user)) => {
+                Some(AdminResponse::Owner(user.clone()))
+            }
+            Some(admin_message::PayloadVariant::GetConfigResponse(config)) => {
+                Some(AdminResponse::Config(config.clone()))
+            }
+            Some(admin_message::PayloadVariant::GetChannelResponse(channel)) => {
+                Some(AdminResponse::Channel(channel.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Session passkeys of the administered nodes, which they send with every
+/// response and require in the requests that change them.
+#[derive(Debug, Default)]
+pub struct AdminSessions {
+    passkeys: HashMap<u32, (Vec<u8>, Instant)>,
+}
+
+impl AdminSessions {
+    pub fn set(&mut self, node_num: u32, passkey: Vec<u8>) {
+        self.passkeys.insert(node_num, (passkey, Instant::now()));
+    }
+
+    /// Passkey of the node, while it is still valid.
+    pub fn passkey(&self, node_num: u32) -> Option<&[u8]> {
+        self.passkeys
+            .get(&node_num)
+            .filter(|(_, received)| received.elapsed() < SESSION_TTL)
+            .map(|(passkey, _)| passkey.as_slice())
+    }
+}
+
+#[test]
+fn test_admin_messages() {
+    let user = User {
+        long_name: "Hill relay".into(),
+        short_name: "hill".into(),
+        ..Default::default()
+    };
+    let message = AdminRequest::SetOwner(user.clone()).to_admin_message(vec![1, 2]);
+    assert_eq!(message.session_passkey, vec![1, 2]);
+    assert_eq!(
+        message.payload_variant,
+        Some(admin_message::PayloadVariant::SetOwner(user.clone()))
+    );
+    assert!(!AdminRequest::Reboot(5).wants_response());
+    assert!(AdminRequest::GetConfig(ConfigType::LoraConfig).wants_response());
+    assert!(AdminRequest::GetChannel(0).wants_response());
+    assert_eq!(
+        AdminRequest::GetChannel(0)
+            .to_admin_message(Vec::new())
+            .payload_variant,
+        Some(admin_message::PayloadVariant::GetChannelRequest(1))
+    );
+    let channel = Channel {
+        index: 1,
+        role: meshtastic::protobufs::channel::Role::Secondary as i32,
+        ..Default::default()
+    };
+    assert!(!AdminRequest::SetChannel(channel.clone()).wants_response());
+    assert_eq!(
+        AdminRequest::SetChannel(channel.clone())
+            .to_admin_message(Vec::new())
+            .payload_variant,
+        Some(admin_message::PayloadVariant::SetChannel(channel.clone()))
+    );
+
+    let response = AdminMessage {
+        payload_variant: Some(admin_message::PayloadVariant::GetOwnerResponse(
+            user.clone(),
+        )),
+        ..Default::default()
+    };
+    assert_eq!(
+        AdminResponse::from_admin_message(&response),
+        Some(AdminResponse::Owner(user))
+    );
+    assert_eq!(AdminResponse::from_admin_message(&message), None);
+    let response = AdminMessage {
+        payload_variant: Some(admin_message::PayloadVariant::GetChannelResponse(
+            channel.clone(),
+        )),
+        ..Default::default()
+    };
+    assert_eq!(
+        AdminResponse::from_admin_message(&response),
+        Some(AdminResponse::Channel(channel))
+    );
+
+    let mut sessions = AdminSessions::default();
+    assert_eq!(sessions.passkey(0xaa), None);
+    sessions.set(0xaa, vec![7]);
+    assert_eq!(sessions.passkey(0xaa), Some(&[7][..]));
+}
@@ -0,0 +1,128 @@
+use anyhow::{Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use meshtastic::{
+    Message,
+    protobufs::{ChannelSet, ChannelSettings, config::lo_ra_config::ModemPreset},
+};
+
+use super::crypto::{ChannelKey, expand_psk};
+
+const URL_PREFIX: &str = "https://meshtastic.org/e/";
+
+/// Channel settings shared as `https://meshtastic.org/e/#<base64 ChannelSet>`,
+/// also accepting the `?add=true` URLs and the bare base64 part.
+pub fn decode(url: &str) -> Result<ChannelSet> {
+    let encoded = match url.trim().split_once('#') {
+        Some((_, encoded)) => encoded,
+        None if !url.contains('/') => url.trim(),
+        None => bail!("Missing '#' in channel URL '{url}'"),
+    };
+    // Some apps pad or use the standard alphabet
+    let encoded: String = encoded
+        .trim_end_matches('=')
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect();
+    let channel_set = ChannelSet::decode(URL_SAFE_NO_PAD.decode(encoded)?.as_slice())?;
+    if channel_set.settings.is_empty() {
+        bail!("No channels in channel URL");
+    }
+    Ok(channel_set)
+}
+
+/// URL of the channel settings, the one QR codes carry. `add` URLs add the
+/// channels to the ones of the radio instead of replacing them.
+pub fn encode(channel_set: &ChannelSet, add: bool) -> String {
+    let encoded = URL_SAFE_NO_PAD.encode(channel_set.encode_to_vec());
+    if add {
+        format!("{URL_PREFIX}?add=true#{encoded}")
+    } else {
+        format!("{URL_PREFIX}#{encoded}")
+    }
+}
+
+/// Display name of a modem preset, such as `LongFast`.
+pub fn preset_name(preset: i32) -> String {
+    let preset = ModemPreset::try_from(preset).unwrap_or(ModemPreset::LongFast);
+    preset
+        .as_str_name()
+        .split('_')
+        .map(|word| word[..1].to_string() + &word[1..].to_lowercase())
+        .collect()
+}
+
+/// Name of a channel, the radio names the unnamed ones after the modem preset.
+pub fn channel_name(channel_set: &ChannelSet, settings: &ChannelSettings) -> String {
+    if settings.name.is_empty() {
+        let preset = channel_set
+            .lora_config
+            .as_ref()
+            .map(|lora| lora.modem_preset)
+            .unwrap_or_default();
+        preset_name(preset)
+    } else {
+        settings.name.clone()
+    }
+}
+
+/// Keys to decrypt the packets of the channels.
+pub fn channel_keys(channel_set: &ChannelSet) -> Result<Vec<ChannelKey>> {
+    channel_set
+        .settings
+        .iter()
+        .map(|settings| {
+            Ok(ChannelKey {
+                name: channel_name(channel_set, settings),
+                key: expand_psk(&settings.psk)?,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_channel_url() -> Result<()> {
+    use meshtastic::protobufs::config::LoRaConfig;
+
+    assert_eq!(preset_name(ModemPreset::LongFast as i32), "LongFast");
+    assert_eq!(
+        preset_name(ModemPreset::VeryLongSlow as i32),
+        "VeryLongSlow"
+    );
+
+    let channel_set = ChannelSet {
+        settings: vec![
+            ChannelSettings {
+                psk: vec![1],
+                ..Default::default()
+            },
+            ChannelSettings {
+                name: "Private".into(),
+                psk: (0..16).collect(),
+                uplink_enabled: true,
+                ..Default::default()
+            },
+        ],
+        lora_config: Some(LoRaConfig {
+            use_preset: true,
+            modem_preset: ModemPreset::MediumFast as i32,
+            hop_limit: 3,
+            ..Default::default()
+        }),
+    };
+    let url = encode(&channel_set, false);
+    let encoded = url.strip_prefix("https://meshtastic.org/e/#").unwrap();
+    assert!(!encoded.contains(['=', '+', '/']));
+    assert_eq!(decode(&url)?, channel_set);
+    assert_eq!(decode(&encode(&channel_set, true))?, channel_set);
+    assert!(decode("https://meshtastic.org/e/").is_err());
+
+    let keys = channel_keys(&channel_set)?;
+    assert_eq!(keys[0].name, "MediumFast");
+    assert_eq!(keys[0].key, "x:AQ==".parse::<ChannelKey>()?.key);
+    assert_eq!((keys[1].name.as_str(), keys[1].key.len()), ("Private", 16));
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use meshtastic::protobufs::{Config, FromRadio, channel, config, from_radio};
+
+use super::channel_url::preset_name;
+
+/// Channels configured in the radio, by index. Packets carry the index of
+/// their channel, the primary one being 0.
+#[derive(Debug, Clone, Default)]
+pub struct Channels {
+    names: BTreeMap<u32, String>,
+    // Unnamed channels are named after the modem preset, sent after them
+    modem_preset: i32,
+}
+
+impl Channels {
+    /// Records the channels and LoRa config the radio sends at boot or when
+    /// they change, returns true when it was one of them.
+    pub fn add_from_radio(&mut self, from_radio: &FromRadio) -> bool {
+        match &from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::Channel(channel)) => {
+                let index = channel.index as u32;
+                match &channel.settings {
+                    Some(settings) if channel.role != channel::Role::Disabled as i32 => {
+                        self.names.insert(index, settings.name.clone());
+                    }
+                    _ => {
+                        self.names.remove(&index);
+                    }
+                }
+                true
+            }
+            Some(from_radio::PayloadVariant::Config(Config {
+                payload_variant: Some(config::PayloadVariant::Lora(lora)),
+            })) => {
+                self.modem_preset = lora.modem_preset;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Name of a channel, `#<index>` when unknown.
+    pub fn name(&self, index: u32) -> String {
+        match self.names.get(&index) {
+            Some(name) if name.is_empty() => preset_name(self.modem_preset),
+            Some(name) => name.clone(),
+            None => format!("#{index}"),
+        }
+    }
+
+    /// `[<name>] ` to tell the channel of a message, empty for the primary one
+    /// where most traffic is.
+    pub fn tag(&self, index: u32) -> String {
+        match index {
+            0 => String::new(),
+            index => format!("[{}] ", self.name(index)),
+        }
+    }
+
+    /// Index of a channel by name, case insensitive, or by index.
+    pub fn index_of(&self, name: &str) -> Option<u32> {
+        let name = name.trim_start_matches('#');
+        if let Ok(index) = name.parse() {
+            return self.names.contains_key(&index).then_some(index);
+        }
+        self.names
+            .keys()
+            .find(|index| self.name(**index).eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    /// Index and name of the enabled channels.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, String)> {
+        self.names.keys().map(|index| (*index, self.name(*index)))
+    }
+}
+
+#[test]
+fn test_channels() {
+    use meshtastic::protobufs::{
+        Channel, ChannelSettings,
+        config::{LoRaConfig, lo_ra_config::ModemPreset},
+    };
+
+    let channel = |index, name: &str, role: channel::Role| FromRadio {
+        payload_variant: Some(from_radio::PayloadVariant::Channel(Channel {
+            index,
+            settings: Some(ChannelSettings {
+                name: name.into(),
+                ..Default::default()
+            }),
+            role: role as i32,
+        })),
+        ..Default::default()
+    };
+    let mut channels = Channels::default();
+    assert!(channels.add_from_radio(&channel(0, "", channel::Role::Primary)));
+    assert!(channels.add_from_radio(&channel(1, "Private", channel::Role::Secondary)));
+    assert!(channels.add_from_radio(&channel(2, "", channel::Role::Disabled)));
+    assert_eq!(channels.name(0), "LongFast");
+    channels.add_from_radio(&FromRadio {
+        payload_variant: Some(from_radio::PayloadVariant::Config(Config {
+            payload_variant: Some(config::PayloadVariant::Lora(LoRaConfig {
+                modem_preset: ModemPreset::MediumFast as i32,
+                ..Default::default()
+            })),
+        })),
+        ..Default::default()
+    });
+
+    assert_eq!(
+        channels.iter().collect::<Vec<_>>(),
+        vec![(0, "MediumFast".to_string()), (1, "Private".to_string())]
+    );
+    assert_eq!(channels.name(2), "#2");
+    assert_eq!(
+        (channels.tag(0).as_str(), channels.tag(1).as_str()),
+        ("", "[Private] ")
+    );
+    assert_eq!(channels.index_of("private"), Some(1));
+    assert_eq!(channels.index_of("#1"), Some(1));
+    assert_eq!(channels.index_of("2"), None);
+}
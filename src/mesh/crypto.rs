@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use aes::{Aes128, Aes256};
+use anyhow::{Result, anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use meshtastic::{
+    Message,
+    protobufs::{Data, MeshPacket, PortNum, mesh_packet},
+};
+
+use super::channel_url;
+
+// Key of the `AQ==` PSK, used by the default LongFast channel
+const DEFAULT_KEY: [u8; 16] = [
+    0xd4, 0xf1, 0xbb, 0x3a, 0x20, 0x29, 0x07, 0x59, 0xf0, 0xbc, 0xff, 0xab, 0xcf, 0x4e, 0x69, 0x01,
+];
+// Name of the primary channel when it has none, from the default modem preset
+const DEFAULT_CHANNEL: &str = "LongFast";
+
+// The radio increments only the last 4 bytes of the nonce
+type Aes128Ctr = ctr::Ctr32BE<Aes128>;
+type Aes256Ctr = ctr::Ctr32BE<Aes256>;
+
+/// AES key of a channel PSK, empty for the channels without encryption.
+pub fn expand_psk(psk: &[u8]) -> Result<Vec<u8>> {
+    Ok(match psk.len() {
+        0 => Vec::new(),
+        // Index of a well-known key, 0 disables the encryption
+        1 if psk[0] == 0 => Vec::new(),
+        1 => {
+            let mut key = DEFAULT_KEY.to_vec();
+            key[15] = key[15].wrapping_add(psk[0] - 1);
+            key
+        }
+        // Short keys are padded with zeros
+        2..=16 => {
+            let mut key = psk.to_vec();
+            key.resize(16, 0);
+            key
+        }
+        17..=32 => {
+            let mut key = psk.to_vec();
+            key.resize(32, 0);
+            key
+        }
+        len => bail!("PSK of {len} bytes, at most 32 are allowed"),
+    })
+}
+
+/// Encrypts or decrypts, AES-CTR being symmetric, the payload of the packet
+/// `packet_id` sent by `from`.
+pub fn apply_keystream(key: &[u8], packet_id: u32, from: u32, payload: &mut [u8]) -> Result<()> {
+    let mut nonce = [0u8; 16];
+    nonce[..8].copy_from_slice(&(packet_id as u64).to_le_bytes());
+    nonce[8..12].copy_from_slice(&from.to_le_bytes());
+    match key.len() {
+        0 => {}
+        16 => Aes128Ctr::new_from_slices(key, &nonce)
+            .map_err(|err| anyhow!("{err}"))?
+            .apply_keystream(payload),
+        32 => Aes256Ctr::new_from_slices(key, &nonce)
+            .map_err(|err| anyhow!("{err}"))?
+            .apply_keystream(payload),
+        len => bail!("AES key of {len} bytes"),
+    }
+    Ok(())
+}
+
+/// Name and key of a channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelKey {
+    pub name: String,
+    // Expanded AES key, empty without encryption
+    pub key: Vec<u8>,
+}
+
+impl FromStr for ChannelKey {
+    type Err = anyhow::Error;
+
+    /// Parses `<name>:<base64 psk>`, e.g. `LongFast:AQ==`.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((name, psk)) = s.split_once(':') else {
+            bail!("Missing ':' in channel key '{s}'");
+        };
+        Ok(Self {
+            name: name.trim().to_string(),
+            key: expand_psk(&BASE64.decode(psk.trim())?)?,
+        })
+    }
+}
+
+impl ChannelKey {
+    /// Hash sent in the `channel` field of the encrypted packets, so receivers
+    /// know which key to try.
+    pub fn hash(&self) -> u32 {
+        let name = if self.name.is_empty() {
+            DEFAULT_CHANNEL
+        } else {
+            &self.name
+        };
+        let xor = |bytes: &[u8]| bytes.iter().fold(0u8, |acc, b| acc ^ b);
+        (xor(name.as_bytes()) ^ xor(&self.key)) as u32
+    }
+
+    /// Decoded payload of an encrypted packet, `None` when this is not its key.
+    pub fn decrypt(&self, mesh_packet: &MeshPacket) -> Option<Data> {
+        let Some(mesh_packet::PayloadVariant::Encrypted(encrypted)) = &mesh_packet.payload_variant
+        else {
+            return None;
+        };
+        let mut payload = encrypted.clone();
+        apply_keystream(&self.key, mesh_packet.id, mesh_packet.from, &mut payload).ok()?;
+        let data = Data::decode(payload.as_slice()).ok()?;
+        // A wrong key yields garbage that rarely decodes to a known application
+        match PortNum::try_from(data.portnum) {
+            Ok(PortNum::UnknownApp) | Err(_) => None,
+            Ok(_) => Some(data),
+        }
+    }
+}
+
+/// Keys of the channels whose packets we decrypt, the default LongFast one
+/// included.
+#[derive(Debug, Clone)]
+pub struct ChannelKeys {
+    keys: Vec<ChannelKey>,
+}
+
+impl Default for ChannelKeys {
+    fn default() -> Self {
+        Self {
+            keys: vec![ChannelKey {
+                name: DEFAULT_CHANNEL.to_string(),
+                key: DEFAULT_KEY.to_vec(),
+            }],
+        }
+    }
+}
+
+impl ChannelKeys {
+    /// The default key plus the ones of `CHANNEL_KEYS`, `<name>:<base64 psk>`
+    /// separated by commas, and of the channel URLs of `CHANNEL_URLS`.
+    pub fn from_env() -> Result<Self> {
+        let mut keys = Self::default();
+        if let Ok(channels) = std::env::var("CHANNEL_KEYS") {
+            for channel in channels.split(',').filter(|c| !c.trim().is_empty()) {
+                keys.add(channel.parse()?);
+            }
+        }
+        if let Ok(urls) = std::env::var("CHANNEL_URLS") {
+            for url in urls.split(',').filter(|u| !u.trim().is_empty()) {
+                for key in channel_url::channel_keys(&channel_url::decode(url)?)? {
+                    keys.add(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    pub fn add(&mut self, key: ChannelKey) {
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    /// Decoded payload of an encrypted packet, trying first the keys whose
+    /// hash matches its channel.
+    pub fn decrypt(&self, mesh_packet: &MeshPacket) -> Option<Data> {
+        let (matching, others): (Vec<_>, Vec<_>) = self
+            .keys
+            .iter()
+            .partition(|key| key.hash() == mesh_packet.channel);
+        matching
+            .into_iter()
+            .chain(others)
+            .find_map(|key| key.decrypt(mesh_packet))
+    }
+
+    /// Replaces the encrypted payload of the packet with the decoded one,
+    /// returns false when no key fits.
+    pub fn decrypt_in_place(&self, mesh_packet: &mut MeshPacket) -> bool {
+        match self.decrypt(mesh_packet) {
+            Some(data) => {
+                mesh_packet.payload_variant = Some(mesh_packet::PayloadVariant::Decoded(data));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn test_decrypt() -> Result<()> {
+    let long_fast: ChannelKey = "LongFast:AQ==".parse()?;
+    assert_eq!(long_fast.key, DEFAULT_KEY);
+    assert_eq!(long_fast.hash(), 8);
+    assert_eq!("x:Ag==".parse::<ChannelKey>()?.key[15], 0x02);
+    assert!("x:AA==".parse::<ChannelKey>()?.key.is_empty());
+    assert!("no-colon".parse::<ChannelKey>().is_err());
+
+    // Text "hi" encrypted by a radio with the default key
+    let mut mesh_packet = MeshPacket {
+        id: 0x12345678,
+        from: 0xaabbccdd,
+        channel: 8,
+        payload_variant: Some(mesh_packet::PayloadVariant::Encrypted(vec![
+            0xc4, 0x3c, 0xcf, 0xbd, 0x2f, 0x50,
+        ])),
+        ..Default::default()
+    };
+    let mut keys = ChannelKeys::default();
+    keys.add("Private:q83vEjRWeJCrze8SNFZ4kA==".parse()?);
+    assert!(keys.decrypt_in_place(&mut mesh_packet));
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &mesh_packet.payload_variant else {
+        bail!("Not decoded");
+    };
+    assert_eq!(data.portnum, PortNum::TextMessageApp as i32);
+    assert_eq!(data.payload, b"hi");
+
+    // Round trip with a 256 bit key
+    let private: ChannelKey = "Private:q83vEjRWeJCrze8SNFZ4kKvN7xI0VniQq83vEjRWeJA=".parse()?;
+    let mut payload = data.encode_to_vec();
+    apply_keystream(&private.key, 7, 0x11, &mut payload)?;
+    let encrypted = MeshPacket {
+        id: 7,
+        from: 0x11,
+        payload_variant: Some(mesh_packet::PayloadVariant::Encrypted(payload)),
+        ..Default::default()
+    };
+    keys.add(private);
+    assert_eq!(keys.decrypt(&encrypted).as_ref(), Some(data));
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Serialize, de::DeserializeOwned};
+
+/// `path` with `suffix` appended to its file name.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn read<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Contents of a JSON file, the default when it does not exist. A damaged
+/// file must not keep the radio from connecting, so it is moved aside to
+/// `<path>.corrupt` for inspection and the default is returned.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match read(path) {
+        Ok(value) => value,
+        Err(err) => {
+            let corrupt = with_suffix(path, ".corrupt");
+            log::error!(
+                "Skipping unreadable {}, moved to {}: {:?}",
+                path.display(),
+                corrupt.display(),
+                err
+            );
+            if let Err(err) = fs::rename(path, &corrupt) {
+                log::warn!("Could not move {} aside: {:?}", path.display(), err);
+            }
+            T::default()
+        }
+    }
+}
+
+/// Writes a temporary file renamed over `path`, so that a crash while saving
+/// never leaves it truncated.
+pub fn save<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[test]
+fn test_json_file() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("mbbs-json-{}.json", std::process::id()));
+    let corrupt = with_suffix(&path, ".corrupt");
+    let _ = fs::remove_file(&path);
+    assert_eq!(load::<Vec<u32>>(&path), Vec::<u32>::new());
+
+    fs::write(&path, b"[1, 2")?;
+    assert_eq!(load::<Vec<u32>>(&path), Vec::<u32>::new());
+    assert!(corrupt.exists() && !path.exists());
+
+    save(&path, &vec![1, 2])?;
+    assert!(!with_suffix(&path, ".tmp").exists());
+    assert_eq!(load::<Vec<u32>>(&path), vec![1, 2]);
+    fs::remove_file(&path)?;
+    fs::remove_file(&corrupt)?;
+    Ok(())
+}
@@ -1,4 +1,16 @@
-mod router;
+mod admin;
+pub mod channel_url;
+mod channels;
+pub mod crypto;
+mod json_file;
+mod nodedb;
+mod outbox;
+mod queue;
 pub mod service;
+#[cfg(test)]
+mod sim;
+mod store_forward;
+mod topology;
+pub mod transport;
 mod types;
-mod utils;
+pub mod utils;
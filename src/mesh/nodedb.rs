@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use meshtastic::{
+    Message,
+    protobufs::{
+        HardwareModel, MeshPacket, NodeInfo, PortNum, User, config::device_config::Role,
+        mesh_packet,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use super::json_file;
+
+#[derive(Debug, Clone)]
+pub struct NodeDbConfig {
+    // File the nodes are saved to, kept in memory only when missing
+    pub path: Option<PathBuf>,
+    // Minimum time between two saves
+    pub save_interval: Duration,
+}
+
+impl Default for NodeDbConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            save_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl NodeDbConfig {
+    /// Reads `NODEDB_FILE`, defaulting to `nodes.json`, and
+    /// `NODEDB_SAVE_INTERVAL_SECS`.
+    pub fn from_env() -> Self {
+        let mut config = Self {
+            path: Some(PathBuf::from(
+                std::env::var("NODEDB_FILE").unwrap_or("nodes.json".into()),
+            )),
+            ..Self::default()
+        };
+        if let Some(secs) = std::env::var("NODEDB_SAVE_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.save_interval = Duration::from_secs(secs);
+        }
+        config
+    }
+}
+
+/// What we know about a node
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    pub num: u32,
+    pub user: Option<User>,
+    // Unix time the node was first and last heard
+    pub first_heard: i64,
+    pub last_heard: i64,
+    // Of the last packet received straight from the node
+    pub snr: Option<f32>,
+    pub rssi: Option<i32>,
+    pub hops_away: Option<u32>,
+}
+
+impl NodeRecord {
+    pub fn short_name(&self) -> Option<&str> {
+        self.user.as_ref().map(|user| user.short_name.as_str())
+    }
+
+    /// Hardware model name, such as `HELTEC_V3`.
+    pub fn hw_model(&self) -> Option<&'static str> {
+        let user = self.user.as_ref()?;
+        HardwareModel::try_from(user.hw_model)
+            .ok()
+            .map(|model| model.as_str_name())
+    }
+
+    /// Device role name, such as `ROUTER`.
+    pub fn role(&self) -> Option<&'static str> {
+        let user = self.user.as_ref()?;
+        Role::try_from(user.role)
+            .ok()
+            .map(|role| role.as_str_name())
+    }
+
+    fn heard(&mut self, time: i64) {
+        if time <= 0 {
+            return;
+        }
+        if self.first_heard == 0 || time < self.first_heard {
+            self.first_heard = time;
+        }
+        self.last_heard = self.last_heard.max(time);
+    }
+}
+
+/// Nodes of the mesh with their user info and radio metrics, saved to disk
+/// every `save_interval` and reloaded at startup.
+#[derive(Debug, Default)]
+pub struct NodeDb {
+    config: NodeDbConfig,
+    nodes: HashMap<u32, NodeRecord>,
+    dirty: bool,
+    last_save: Option<Instant>,
+}
+
+impl NodeDb {
+    pub fn load(config: NodeDbConfig) -> Result<Self> {
+        let records: Vec<NodeRecord> = match &config.path {
+            Some(path) => json_file::load(path),
+            None => Vec::new(),
+        };
+        Ok(Self {
+            config,
+            nodes: records.into_iter().map(|r| (r.num, r)).collect(),
+            dirty: false,
+            last_save: None,
+        })
+    }
+
+    /// Writes the nodes if they changed, whatever the time since the last save.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut records: Vec<&NodeRecord> = self.nodes.values().collect();
+        records.sort_by_key(|r| r.num);
+        json_file::save(path, &records)?;
+        self.dirty = false;
+        self.last_save = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Writes the nodes if they changed and `save_interval` has passed.
+    pub fn save_if_due(&mut self) -> Result<()> {
+        if self
+            .last_save
+            .is_some_and(|last| last.elapsed() < self.config.save_interval)
+        {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    pub fn get(&self, node_num: u32) -> Option<&NodeRecord> {
+        self.nodes.get(&node_num)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &NodeRecord> {
+        self.nodes.values()
+    }
+
+    pub fn user(&self, node_num: u32) -> Option<&User> {
+        self.get(node_num)?.user.as_ref()
+    }
+
+    /// Nodes whose user info is known.
+    pub fn users(&self) -> impl Iterator<Item = (u32, &User)> {
+        self.nodes
+            .values()
+            .filter_map(|r| Some((r.num, r.user.as_ref()?)))
+    }
+
+    /// Long name of a node, its `!aabbccdd` id when unknown.
+    pub fn long_name_of(&self, node_num: u32) -> String {
+        match self.user(node_num) {
+            Some(user) => user.long_name.clone(),
+            None => format!("!{:08x}", node_num),
+        }
+    }
+
+    fn record(&mut self, node_num: u32) -> &mut NodeRecord {
+        self.dirty = true;
+        self.nodes.entry(node_num).or_insert_with(|| NodeRecord {
+            num: node_num,
+            ..Default::default()
+        })
+    }
+
+    pub fn set_user(&mut self, node_num: u32, user: User) {
+        self.record(node_num).user = Some(user);
+    }
+
+    /// Entry of the radio NodeDB.
+    pub fn add_node_info(&mut self, node_info: &NodeInfo) {
+        let record = self.record(node_info.num);
+        if let Some(user) = &node_info.user {
+            record.user = Some(user.clone());
+        }
+        if node_info.last_heard as i64 > record.last_heard {
+            if node_info.snr != 0.0 {
+                record.snr = Some(node_info.snr);
+            }
+            if node_info.hops_away.is_some() {
+                record.hops_away = node_info.hops_away;
+            }
+        }
+        record.heard(node_info.last_heard as i64);
+    }
+
+    /// Packet received at `time`, with the user info of the NodeInfo packets.
+    pub fn add_packet(&mut self, mesh_packet: &MeshPacket, time: i64) {
+        let record = self.record(mesh_packet.from);
+        record.heard(time);
+        // Packets of firmware older than 2.3 have no hop_start
+        if mesh_packet.hop_start > 0 && mesh_packet.hop_start >= mesh_packet.hop_limit {
+            let hops = mesh_packet.hop_start - mesh_packet.hop_limit;
+            record.hops_away = Some(hops);
+            // Relayed packets carry the metrics of the last hop
+            if hops == 0 {
+                if mesh_packet.rx_snr != 0.0 {
+                    record.snr = Some(mesh_packet.rx_snr);
+                }
+                if mesh_packet.rx_rssi != 0 {
+                    record.rssi = Some(mesh_packet.rx_rssi);
+                }
+            }
+        }
+        if let Some(mesh_packet::PayloadVariant::Decoded(data)) = &mesh_packet.payload_variant
+            && data.portnum == PortNum::NodeinfoApp as i32
+            && let Ok(user) = User::decode(data.payload.as_slice())
+        {
+            record.user = Some(user);
+        }
+    }
+}
+
+#[test]
+fn test_nodedb() -> Result<()> {
+    use meshtastic::protobufs::Data;
+
+    let path = std::env::temp_dir().join(format!("mbbs-nodedb-{}.json", std::process::id()));
+    let config = NodeDbConfig {
+        path: Some(path.clone()),
+        save_interval: Duration::from_secs(3600),
+    };
+    let mut nodes = NodeDb::load(config.clone())?;
+    nodes.add_node_info(&NodeInfo {
+        num: 0xaa,
+        user: Some(User {
+            short_name: "aa".into(),
+            long_name: "Alpha".into(),
+            hw_model: HardwareModel::HeltecV3 as i32,
+            role: Role::Router as i32,
+            ..Default::default()
+        }),
+        last_heard: 1_700_000_000,
+        snr: 4.5,
+        hops_away: Some(2),
+        ..Default::default()
+    });
+    let user = User {
+        short_name: "bb".into(),
+        ..Default::default()
+    };
+    nodes.add_packet(
+        &MeshPacket {
+            from: 0xbb,
+            hop_start: 3,
+            hop_limit: 3,
+            rx_snr: -6.25,
+            rx_rssi: -110,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+                portnum: PortNum::NodeinfoApp as i32,
+                payload: user.encode_to_vec(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        1_700_000_100,
+    );
+    nodes.save_if_due()?;
+    // Saved less than `save_interval` ago
+    nodes.add_packet(
+        &MeshPacket {
+            from: 0xaa,
+            ..Default::default()
+        },
+        1_700_000_200,
+    );
+    nodes.save_if_due()?;
+
+    let loaded = NodeDb::load(config)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(loaded.iter().count(), 2);
+    let aa = loaded.get(0xaa).unwrap();
+    assert_eq!(
+        (aa.first_heard, aa.last_heard),
+        (1_700_000_000, 1_700_000_000)
+    );
+    assert_eq!(
+        (aa.hw_model(), aa.role()),
+        (Some("HELTEC_V3"), Some("ROUTER"))
+    );
+    assert_eq!((aa.snr, aa.hops_away), (Some(4.5), Some(2)));
+    let bb = loaded.get(0xbb).unwrap();
+    assert_eq!(bb.short_name(), Some("bb"));
+    assert_eq!(
+        (bb.snr, bb.rssi, bb.hops_away),
+        (Some(-6.25), Some(-110), Some(0))
+    );
+    assert_eq!(loaded.long_name_of(0xcc), "!000000cc");
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{json_file, types::TextMessage};
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    pub path: PathBuf,
+    pub max_age: Duration,
+    pub max_attempts: u32,
+    // Minimum time between two delivery attempts of the same message
+    pub retry_interval: Duration,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("outbox.json"),
+            max_age: Duration::from_secs(48 * 3600),
+            max_attempts: 5,
+            retry_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl OutboxConfig {
+    /// Reads `OUTBOX_FILE`, `OUTBOX_MAX_AGE_SECS`, `OUTBOX_MAX_ATTEMPTS` and
+    /// `OUTBOX_RETRY_INTERVAL_SECS`, using the defaults for the missing ones.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(path) = std::env::var("OUTBOX_FILE") {
+            config.path = PathBuf::from(path);
+        }
+        if let Some(secs) = std::env::var("OUTBOX_MAX_AGE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.max_age = Duration::from_secs(secs);
+        }
+        if let Some(attempts) = std::env::var("OUTBOX_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.max_attempts = attempts;
+        }
+        if let Some(secs) = std::env::var("OUTBOX_RETRY_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.retry_interval = Duration::from_secs(secs);
+        }
+        config
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingMessage {
+    pub id: u64,
+    pub to: u32,
+    pub text: String,
+    // Index of the channel it was sent in, older outboxes only had the primary one
+    #[serde(default)]
+    pub channel: u32,
+    // Node the BBS relays it for
+    #[serde(default)]
+    pub origin: Option<u32>,
+    // Unix timestamp when the message was queued
+    pub created: i64,
+    // Unix timestamp of the last delivery attempt
+    pub last_attempt: i64,
+    pub attempts: u32,
+}
+
+/// Direct messages that could not be delivered, waiting for the destination
+/// node to be heard again. Saved to disk on every change.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    config: OutboxConfig,
+    next_id: u64,
+    messages: Vec<PendingMessage>,
+}
+
+fn now_ts() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+impl Outbox {
+    pub fn load(config: OutboxConfig) -> Result<Self> {
+        let messages: Vec<PendingMessage> = json_file::load(&config.path);
+        let next_id = messages.iter().map(|m| m.id + 1).max().unwrap_or(0);
+        let mut outbox = Self {
+            config,
+            next_id,
+            messages,
+        };
+        outbox.expire()?;
+        Ok(outbox)
+    }
+
+    fn save(&self) -> Result<()> {
+        json_file::save(&self.config.path, &self.messages)
+    }
+
+    /// Queues a message whose first delivery attempt failed.
+    pub fn push(&mut self, msg: &TextMessage) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let now = now_ts();
+        self.messages.push(PendingMessage {
+            id,
+            to: msg.to,
+            text: msg.text.clone(),
+            channel: msg.channel,
+            origin: msg.origin,
+            created: now,
+            last_attempt: now,
+            attempts: 1,
+        });
+        self.save()?;
+        Ok(id)
+    }
+
+    /// Messages for `to` ready to be sent again, counted as a new attempt.
+    pub fn due_for(&mut self, to: u32) -> Result<Vec<PendingMessage>> {
+        self.expire()?;
+        let now = now_ts();
+        let retry_interval = self.config.retry_interval.as_secs() as i64;
+        let mut due = Vec::new();
+        for msg in self.messages.iter_mut() {
+            if msg.to == to && now - msg.last_attempt >= retry_interval {
+                msg.attempts += 1;
+                msg.last_attempt = now;
+                due.push(msg.clone());
+            }
+        }
+        if !due.is_empty() {
+            self.save()?;
+        }
+        Ok(due)
+    }
+
+    /// Removes a message once it has been acknowledged.
+    pub fn delivered(&mut self, id: u64) -> Result<()> {
+        self.messages.retain(|m| m.id != id);
+        self.save()
+    }
+
+    pub fn pending_for(&self, to: u32) -> Vec<&PendingMessage> {
+        self.messages.iter().filter(|m| m.to == to).collect()
+    }
+
+    /// Messages relayed for `origin` still waiting for their destination.
+    pub fn pending_from(&self, origin: u32) -> Vec<&PendingMessage> {
+        self.messages
+            .iter()
+            .filter(|m| m.origin == Some(origin))
+            .collect()
+    }
+
+    fn expire(&mut self) -> Result<()> {
+        let min_created = now_ts() - self.config.max_age.as_secs() as i64;
+        let max_attempts = self.config.max_attempts;
+        let before = self.messages.len();
+        self.messages.retain(|m| {
+            let keep = m.created >= min_created && m.attempts < max_attempts;
+            if !keep {
+                log::warn!("Giving up delivery of {:?}", m);
+            }
+            keep
+        });
+        if self.messages.len() != before {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_corrupt_outbox() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("mbbs-outbox-{}.json", std::process::id()));
+    let corrupt = json_file::with_suffix(&path, ".corrupt");
+    std::fs::write(&path, b"{\"truncated")?;
+    let config = OutboxConfig {
+        path: path.clone(),
+        ..Default::default()
+    };
+
+    let mut outbox = Outbox::load(config.clone())?;
+    assert!(corrupt.exists());
+    let mut msg = TextMessage::sent(0x11, 0xaa, "hello".into());
+    msg.origin = Some(0xbb);
+    outbox.push(&msg)?;
+    assert!(!json_file::with_suffix(&path, ".tmp").exists());
+
+    let outbox = Outbox::load(config)?;
+    assert_eq!(outbox.pending_for(0xaa)[0].text, "hello");
+    assert_eq!(outbox.pending_from(0xbb)[0].to, 0xaa);
+    std::fs::remove_file(&path)?;
+    std::fs::remove_file(&corrupt)?;
+    Ok(())
+}
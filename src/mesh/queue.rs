@@ -0,0 +1,218 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use meshtastic::protobufs::Data;
+use tokio::sync::oneshot;
+
+use super::types::{MessagePriority, TextMessage, TextMessageStatus};
+
+// Waiting this long raises a message one priority class, so low priority
+// traffic is not starved by a constant flow of higher priority messages
+const AGING_STEP: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct PacingConfig {
+    // Minimum time between two transmissions
+    pub min_spacing: Duration,
+    // Maximum transmissions in any 60s window
+    pub max_per_minute: usize,
+}
+
+impl Default for PacingConfig {
+    fn default() -> Self {
+        Self {
+            min_spacing: Duration::from_secs(3),
+            max_per_minute: 10,
+        }
+    }
+}
+
+impl PacingConfig {
+    /// Reads `TX_MIN_SPACING_MS` and `TX_MAX_PER_MINUTE`, using the defaults
+    /// for the missing ones.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(ms) = std::env::var("TX_MIN_SPACING_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.min_spacing = Duration::from_millis(ms);
+        }
+        if let Some(max) = std::env::var("TX_MAX_PER_MINUTE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.max_per_minute = max;
+        }
+        config
+    }
+}
+
+#[derive(Debug)]
+pub struct QueuedMessage {
+    pub msg: TextMessage,
+    // Sent instead of the text, for requests of other applications
+    pub data: Option<Data>,
+    // Set when the message is a retry of an outbox entry
+    pub outbox_id: Option<u64>,
+    // Receives the final delivery status of the message
+    pub confirm: Option<oneshot::Sender<TextMessageStatus>>,
+}
+
+/// Outgoing messages, released highest priority first at a pace that
+/// respects the LoRa duty cycle.
+#[derive(Debug, Default)]
+pub struct OutgoingQueue {
+    config: PacingConfig,
+    queue: VecDeque<(Instant, QueuedMessage)>,
+    sent: VecDeque<Instant>,
+}
+
+impl OutgoingQueue {
+    pub fn new(config: PacingConfig) -> Self {
+        Self {
+            config,
+            queue: VecDeque::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, msg: QueuedMessage) {
+        self.push_at(Instant::now(), msg);
+    }
+
+    fn push_at(&mut self, queued: Instant, msg: QueuedMessage) {
+        self.queue.push_back((queued, msg));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Next message to transmit, if the pacing limits allow sending at `now`.
+    pub fn pop_ready(&mut self, now: Instant) -> Option<QueuedMessage> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        while let Some(ts) = self.sent.front()
+            && now.duration_since(*ts) >= Duration::from_secs(60)
+        {
+            self.sent.pop_front();
+        }
+        if let Some(last) = self.sent.back()
+            && now.duration_since(*last) < self.config.min_spacing
+        {
+            return None;
+        }
+        if self.sent.len() >= self.config.max_per_minute {
+            return None;
+        }
+        let next = self.next_index(now)?;
+        self.sent.push_back(now);
+        self.queue.remove(next).map(|(_, msg)| msg)
+    }
+
+    /// Index of the message with the highest aged priority, oldest first on ties.
+    fn next_index(&self, now: Instant) -> Option<usize> {
+        let aged = |queued: &Instant, priority: MessagePriority| {
+            let waited = now.saturating_duration_since(*queued);
+            priority as u64 + waited.as_secs() / AGING_STEP.as_secs()
+        };
+        let mut best: Option<(usize, u64)> = None;
+        for (index, (queued, msg)) in self.queue.iter().enumerate() {
+            let level = aged(queued, msg.msg.priority);
+            if best.is_none_or(|(_, best_level)| level > best_level) {
+                best = Some((index, level));
+            }
+        }
+        best.map(|(index, _)| index)
+    }
+}
+
+#[test]
+fn test_pacing() {
+    let mut queue = OutgoingQueue::new(PacingConfig {
+        min_spacing: Duration::from_secs(2),
+        max_per_minute: 3,
+    });
+    for n in 0..5 {
+        queue.push(QueuedMessage {
+            msg: TextMessage::sent(1, 2, format!("msg {n}")),
+            data: None,
+            outbox_id: None,
+            confirm: None,
+        });
+    }
+
+    let t0 = Instant::now();
+    assert_eq!(queue.pop_ready(t0).unwrap().msg.text, "msg 0");
+    assert!(queue.pop_ready(t0 + Duration::from_secs(1)).is_none());
+    assert_eq!(
+        queue
+            .pop_ready(t0 + Duration::from_secs(2))
+            .unwrap()
+            .msg
+            .text,
+        "msg 1"
+    );
+    assert_eq!(
+        queue
+            .pop_ready(t0 + Duration::from_secs(4))
+            .unwrap()
+            .msg
+            .text,
+        "msg 2"
+    );
+    // Three packets already sent in the last minute
+    assert!(queue.pop_ready(t0 + Duration::from_secs(30)).is_none());
+    assert_eq!(
+        queue
+            .pop_ready(t0 + Duration::from_secs(60))
+            .unwrap()
+            .msg
+            .text,
+        "msg 3"
+    );
+    assert_eq!(queue.len(), 1);
+}
+
+#[test]
+fn test_priority() {
+    let mut queue = OutgoingQueue::new(PacingConfig {
+        min_spacing: Duration::ZERO,
+        max_per_minute: 100,
+    });
+    let msg = |text: &str, priority| {
+        let mut msg = TextMessage::sent(1, 2, text.to_string());
+        msg.priority = priority;
+        QueuedMessage {
+            msg,
+            data: None,
+            outbox_id: None,
+            confirm: None,
+        }
+    };
+    queue.push(msg("bulk", MessagePriority::Background));
+    queue.push(msg("reply", MessagePriority::Normal));
+    queue.push(msg("sos", MessagePriority::Alert));
+    queue.push(msg("reply2", MessagePriority::Normal));
+
+    let now = Instant::now();
+    assert_eq!(queue.pop_ready(now).unwrap().msg.text, "sos");
+    assert_eq!(queue.pop_ready(now).unwrap().msg.text, "reply");
+    assert_eq!(queue.pop_ready(now).unwrap().msg.text, "reply2");
+    assert_eq!(queue.pop_ready(now).unwrap().msg.text, "bulk");
+
+    // A background message waiting long enough goes before a fresh normal one
+    let later = now + AGING_STEP * 2;
+    queue.push_at(now, msg("bulk", MessagePriority::Background));
+    queue.push_at(later, msg("reply", MessagePriority::Normal));
+    assert_eq!(queue.pop_ready(later).unwrap().msg.text, "bulk");
+    assert_eq!(queue.pop_ready(later).unwrap().msg.text, "reply");
+}
@@ -1,9 +1,9 @@
 use anyhow::{Result, anyhow, bail};
 use log::error;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::HashMap,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -17,21 +17,25 @@ use tokio_util::sync::CancellationToken;
 
 use meshtastic::{
     Message,
-    api::{ConnectedStreamApi, StreamApi, StreamHandle, state::Configured},
-    packet::PacketDestination,
+    api::{ConnectedStreamApi, StreamHandle, state::Configured},
     protobufs::{
-        Data, FromRadio, MeshPacket, MyNodeInfo, PortNum, Routing, User, from_radio,
+        AdminMessage, Data, FromRadio, MeshPacket, MyNodeInfo, PortNum, Position, RouteDiscovery,
+        Routing, StoreAndForward, Telemetry, User, from_radio,
         mesh_packet::{self, Priority},
-        routing,
-    },
-    types::{MeshChannel, NodeId},
-    utils::{
-        generate_rand_id,
-        stream::{BleId, build_ble_stream},
+        routing, to_radio,
     },
+    utils::generate_rand_id,
 };
 
-use super::router::*;
+pub use super::admin::*;
+pub use super::channels::*;
+pub use super::nodedb::*;
+pub use super::outbox::*;
+pub use super::queue::*;
+pub use super::store_forward::*;
+pub use super::topology::*;
+pub use super::transport::Transport;
+use super::transport::{Connection, configure};
 pub use super::types::*;
 
 macro_rules! r {
@@ -53,27 +57,81 @@ macro_rules! check {
 }
 use TextMessageStatus::*;
 
+const DEFAULT_HOP_LIMIT: u32 = 3;
+// Positions kept per node
+const POSITION_HISTORY: usize = 256;
+// Telemetry samples kept per node
+const TELEMETRY_HISTORY: usize = 256;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Status {
     Heartbeat(usize),
     Ready,
     NewMessage(u32),
     UpdatedMessage(u32),
+    // Node and index of the channel it was heard on
+    NodeHeard(u32, u32),
+    NewPosition(u32),
+    NewTelemetry(u32),
+    Traceroute(Traceroute),
+    Admin(u32, AdminResponse),
+    QueueDepth(usize),
     FromRadio(FromRadio),
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ServiceConfig {
+    pub outbox: OutboxConfig,
+    pub pacing: PacingConfig,
+    pub confirm: ConfirmConfig,
+    pub nodedb: NodeDbConfig,
+    pub store_forward: StoreForwardConfig,
+}
+
+impl ServiceConfig {
+    pub fn from_env() -> Self {
+        Self {
+            outbox: OutboxConfig::from_env(),
+            pacing: PacingConfig::from_env(),
+            confirm: ConfirmConfig::from_env(),
+            nodedb: NodeDbConfig::from_env(),
+            store_forward: StoreForwardConfig::from_env(),
+        }
+    }
+
+    /// Environment config of the commands that connect to query the radio,
+    /// leaving the node database and the Store & Forward history alone.
+    pub fn one_shot() -> Self {
+        Self {
+            nodedb: NodeDbConfig::default(),
+            store_forward: StoreForwardConfig::default(),
+            ..Self::from_env()
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct HandlerState {
     pub my_node_info: Option<MyNodeInfo>,
-    pub nodes: HashMap<u32, User>,
+    pub nodes: NodeDb,
     pub messages: HashMap<u32, TextMessage>,
+    pub positions: HashMap<u32, Vec<NodePosition>>,
+    pub telemetry: HashMap<u32, Vec<NodeTelemetry>>,
+    // Last traceroute to each node
+    pub traceroutes: HashMap<u32, Traceroute>,
+    pub topology: Topology,
+    pub channels: Channels,
+    pub admin_sessions: AdminSessions,
+    pub store_forward: StoreForward,
+    pub outbox: Outbox,
 }
 
 pub type State = Arc<RwLock<HandlerState>>;
 
 pub struct Handler {
     pub state: State,
-    pub msg_tx: UnboundedSender<TextMessage>,
+    pub msg_tx: UnboundedSender<QueuedMessage>,
+    pub confirm: ConfirmConfig,
     pub status_rx: UnboundedReceiver<Status>,
 
     pub cancel: CancellationToken,
@@ -85,23 +143,38 @@ pub struct Service {
     cancel: CancellationToken,
     packet_rx: UnboundedReceiver<FromRadio>,
     stream_api: ConnectedStreamApi<Configured>,
-    msg_rx: UnboundedReceiver<TextMessage>,
+    msg_rx: UnboundedReceiver<QueuedMessage>,
     status_tx: UnboundedSender<Status>,
     finished_tx: tokio::sync::oneshot::Sender<()>,
     config_complete: bool,
+    queue: OutgoingQueue,
+    // Packet id of the resent messages to their outbox id and transmission time
+    in_flight: HashMap<u32, (u64, Instant)>,
+    // Packet id of the confirmed messages to their transmission time and waiter
+    waiters: HashMap<u32, (Instant, oneshot::Sender<TextMessageStatus>)>,
+    ack_timeout: Duration,
+    // Last Store & Forward heartbeat sent
+    store_forward_heartbeat: Option<Instant>,
 }
 
 impl HandlerState {
     pub fn get_long_name_by_node_id(&self, user_id: u32) -> Option<String> {
-        self.nodes.get(&user_id).map(|user| user.long_name.clone())
+        self.nodes.user(user_id).map(|user| user.long_name.clone())
     }
     pub fn get_short_name_by_node_id(&self, user_id: u32) -> Option<String> {
-        self.nodes.get(&user_id).map(|user| user.long_name.clone())
+        self.nodes.user(user_id).map(|user| user.long_name.clone())
+    }
+    /// Short name of a node, its `!aabbccdd` id when unknown.
+    pub fn node_label(&self, node_id: u32) -> String {
+        match self.nodes.user(node_id) {
+            Some(user) => user.short_name.clone(),
+            None => format!("!{:08x}", node_id),
+        }
     }
     pub fn get_node_id_by_short_name(&self, short_name: &str) -> Option<u32> {
-        for (id, user) in &self.nodes {
+        for (id, user) in self.nodes.users() {
             if user.short_name == short_name {
-                return Some(*id);
+                return Some(id);
             }
         }
         None
@@ -122,13 +195,22 @@ impl HandlerState {
             RoutingError(error) => format!("❌ {:?}", error),
         };
 
+        let channel = self.channels.tag(msg.channel);
+
         if msg.to == 0xffffffff {
-            format!("💬 {} : {} {} ", name(msg.from), msg.text, status)
+            format!(
+                "💬 {}{} : {} {} ",
+                channel,
+                name(msg.from),
+                msg.text,
+                status
+            )
         } else if msg.to == me {
-            format!("👤 {} : {} {}", name(msg.from), msg.text, status)
+            format!("👤 {}{} : {} {}", channel, name(msg.from), msg.text, status)
         } else {
             format!(
-                "📩 {} → {} : {} {}",
+                "📩 {}{} → {} : {} {}",
+                channel,
                 name(msg.from),
                 name(msg.to),
                 msg.text,
@@ -137,6 +219,39 @@ impl HandlerState {
         }
     }
 
+    /// Appends a position to the history of the node, returns false when it
+    /// repeats the last one.
+    pub fn add_position(&mut self, node_id: u32, position: NodePosition) -> bool {
+        let history = self.positions.entry(node_id).or_default();
+        if history.last() == Some(&position) {
+            return false;
+        }
+        history.push(position);
+        if history.len() > POSITION_HISTORY {
+            history.remove(0);
+        }
+        true
+    }
+    pub fn last_position(&self, node_id: u32) -> Option<&NodePosition> {
+        self.positions
+            .get(&node_id)
+            .and_then(|history| history.last())
+    }
+
+    pub fn add_telemetry(&mut self, node_id: u32, sample: NodeTelemetry) {
+        let history = self.telemetry.entry(node_id).or_default();
+        history.push(sample);
+        if history.len() > TELEMETRY_HISTORY {
+            history.remove(0);
+        }
+    }
+    /// Latest value of each metric reported by the node.
+    pub fn latest_telemetry(&self, node_id: u32) -> Option<NodeTelemetry> {
+        self.telemetry
+            .get(&node_id)
+            .and_then(|history| NodeTelemetry::latest(history))
+    }
+
     pub async fn msg(&self, id: u32) -> Option<TextMessage> {
         self.messages.get(&id).cloned()
     }
@@ -173,31 +288,194 @@ impl Handler {
         }
         Ok(())
     }
+    /// Sends a text message on the channel of index `channel`, 0 being the
+    /// primary one.
     pub async fn send_text<T: Into<String>, D: Into<Destination>>(
         &self,
         text: T,
         to: D,
+        channel: u32,
+    ) -> Result<()> {
+        self.send_text_with_priority(text, to, channel, MessagePriority::Normal)
+            .await
+    }
+    pub async fn send_text_with_priority<T: Into<String>, D: Into<Destination>>(
+        &self,
+        text: T,
+        to: D,
+        channel: u32,
+        priority: MessagePriority,
+    ) -> Result<()> {
+        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+        let to = self.resolve_destination(to.into()).await?;
+        let mut msg = TextMessage::sent(from, to, text.into());
+        msg.priority = priority;
+        msg.channel = channel;
+        self.msg_tx.send(QueuedMessage {
+            msg,
+            data: None,
+            outbox_id: None,
+            confirm: None,
+        })?;
+        Ok(())
+    }
+    /// Sends a direct message on behalf of the node `origin`, which can look
+    /// it up in the outbox while it is waiting for its destination.
+    pub async fn relay_text<T: Into<String>>(
+        &self,
+        text: T,
+        to: u32,
+        channel: u32,
+        origin: u32,
+    ) -> Result<()> {
+        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+        let mut msg = TextMessage::sent(from, to, text.into());
+        msg.channel = channel;
+        msg.origin = Some(origin);
+        self.msg_tx.send(QueuedMessage {
+            msg,
+            data: None,
+            outbox_id: None,
+            confirm: None,
+        })?;
+        Ok(())
+    }
+    /// Sends a text message and waits for its final delivery status, sending it
+    /// again up to `confirm.retries` times when it fails or is not acknowledged
+    /// within `confirm.ack_timeout` of being transmitted. Direct messages that
+    /// fail every attempt are moved to the outbox.
+    pub async fn send_text_confirmed<T: Into<String>, D: Into<Destination>>(
+        &self,
+        text: T,
+        to: D,
+        channel: u32,
+        priority: MessagePriority,
+    ) -> Result<DeliveryResult> {
+        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+        let to = self.resolve_destination(to.into()).await?;
+        let mut msg = TextMessage::sent(from, to, text.into());
+        msg.priority = priority;
+        msg.channel = channel;
+
+        let mut result = DeliveryResult::Timeout;
+        for _ in 0..=self.confirm.retries {
+            let (confirm_tx, confirm_rx) = oneshot::channel();
+            self.msg_tx.send(QueuedMessage {
+                msg: msg.clone(),
+                data: None,
+                outbox_id: None,
+                confirm: Some(confirm_tx),
+            })?;
+            result = match confirm_rx.await {
+                Ok(RoutingError(error)) => DeliveryResult::Failed(error),
+                Ok(Sent) => DeliveryResult::Timeout,
+                Ok(_) => return Ok(DeliveryResult::Delivered),
+                Err(_) => bail!("Service finished"),
+            };
+        }
+        if matches!(result, DeliveryResult::Failed(_)) && to != 0xffffffff {
+            w!(self.outbox).push(&msg)?;
+        }
+        Ok(result)
+    }
+    /// Asks the route to a node, the response arrives as `Status::Traceroute`.
+    pub async fn send_traceroute<D: Into<Destination>>(&self, to: D) -> Result<()> {
+        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+        let to = self.resolve_destination(to.into()).await?;
+        self.msg_tx.send(QueuedMessage {
+            msg: TextMessage::sent(from, to, String::new()),
+            data: Some(Data {
+                portnum: PortNum::TracerouteApp as i32,
+                payload: RouteDiscovery::default().encode_to_vec(),
+                want_response: true,
+                ..Default::default()
+            }),
+            outbox_id: None,
+            confirm: None,
+        })?;
+        Ok(())
+    }
+    /// Sends an admin request to a node and returns its response, getting
+    /// first its owner when there is no session passkey to change it. Waits
+    /// up to `timeout` for each response, dropping the other statuses.
+    pub async fn admin(
+        &mut self,
+        to: u32,
+        request: AdminRequest,
+        timeout: Duration,
+    ) -> Result<Option<AdminResponse>> {
+        if !request.wants_response() && r!(self.admin_sessions).passkey(to).is_none() {
+            self.queue_admin(to, &AdminRequest::GetOwner, None).await?;
+            self.admin_response(to, timeout).await?;
+        }
+        if request.wants_response() {
+            self.queue_admin(to, &request, None).await?;
+            return Ok(Some(self.admin_response(to, timeout).await?));
+        }
+        let (confirm_tx, confirm_rx) = oneshot::channel();
+        self.queue_admin(to, &request, Some(confirm_tx)).await?;
+        match confirm_rx.await {
+            Ok(ExplicitAck) => Ok(None),
+            Ok(RoutingError(error)) => bail!("Admin request failed: {:?}", error),
+            Ok(_) => bail!("No acknowledgement from !{:08x}", to),
+            Err(_) => bail!("Service finished"),
+        }
+    }
+    async fn queue_admin(
+        &self,
+        to: u32,
+        request: &AdminRequest,
+        confirm: Option<oneshot::Sender<TextMessageStatus>>,
     ) -> Result<()> {
         let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
-        let to = match to.into() {
+        let passkey = r!(self.admin_sessions)
+            .passkey(to)
+            .map(|passkey| passkey.to_vec())
+            .unwrap_or_default();
+        self.msg_tx.send(QueuedMessage {
+            msg: TextMessage::sent(from, to, String::new()),
+            data: Some(Data {
+                portnum: PortNum::AdminApp as i32,
+                payload: request.to_admin_message(passkey).encode_to_vec(),
+                want_response: request.wants_response(),
+                ..Default::default()
+            }),
+            outbox_id: None,
+            confirm,
+        })?;
+        Ok(())
+    }
+    async fn admin_response(&mut self, from: u32, timeout: Duration) -> Result<AdminResponse> {
+        let response = tokio::time::timeout(timeout, async {
+            loop {
+                match self.status_rx.recv().await {
+                    Some(Status::Admin(node_id, response)) if node_id == from => {
+                        return Ok(response);
+                    }
+                    Some(_) => {}
+                    None => bail!("Channel closed"),
+                }
+            }
+        })
+        .await;
+        response.map_err(|_| anyhow!("No admin response from !{:08x}", from))?
+    }
+    async fn resolve_destination(&self, to: Destination) -> Result<u32> {
+        let to = match to {
             Destination::Node(node_num) => node_num,
             Destination::Broadcast => 0xffffffff,
             Destination::ShortName(short_name) => {
-                let mut id = None;
-                for (node_id, node) in &r!(self.nodes) {
-                    if node.short_name == short_name {
-                        id = Some(*node_id);
-                        break;
-                    }
-                }
-                let Some(id) = id else {
+                let Some(id) = r!(self.nodes)
+                    .users()
+                    .find(|(_, node)| node.short_name == short_name)
+                    .map(|(node_id, _)| node_id)
+                else {
                     bail!("Node '{short_name}' not found")
                 };
                 id
             }
         };
-        self.msg_tx.send(TextMessage::sent(from, to, text.into()))?;
-        Ok(())
+        Ok(to)
     }
     pub async fn finish(mut self) {
         self.cancel.cancel();
@@ -212,36 +490,79 @@ impl Handler {
     }
 }
 
+/// Reception time of the packet, now when the radio did not set it.
+pub(crate) fn rx_time(mesh_packet: &MeshPacket) -> i64 {
+    if mesh_packet.rx_time > 0 {
+        mesh_packet.rx_time as i64
+    } else {
+        chrono::Utc::now().timestamp()
+    }
+}
+
 impl Service {
+    /// Connects to the radio of `transport` and starts the service.
+    pub async fn from_transport(transport: &Transport) -> Result<Handler> {
+        Self::from_transport_with_config(transport, ServiceConfig::from_env()).await
+    }
+
+    pub async fn from_transport_with_config(
+        transport: &Transport,
+        config: ServiceConfig,
+    ) -> Result<Handler> {
+        Self::with_connection(transport.connect().await?, config)
+    }
+
     pub async fn from_ble(ble_device: &str) -> Result<Handler> {
-        let ble_stream =
-            build_ble_stream(&BleId::from_name(&ble_device), Duration::from_secs(5)).await?;
-        Self::build(ble_stream).await
+        Self::from_transport(&Transport::Ble(ble_device.to_string())).await
+    }
+
+    /// Connects to a meshtasticd instance or WiFi radio at `host:port`.
+    pub async fn from_tcp(address: &str) -> Result<Handler> {
+        Self::from_transport(&Transport::Tcp(address.to_string())).await
+    }
+
+    /// Connects to a radio attached by cable, such as `/dev/ttyUSB0`.
+    pub async fn from_serial(device: &str, baud_rate: u32) -> Result<Handler> {
+        let transport = Transport::Serial {
+            device: device.to_string(),
+            baud_rate,
+        };
+        Self::from_transport(&transport).await
     }
 
-    async fn build<S>(stream_handle: StreamHandle<S>) -> Result<Handler>
+    pub(crate) async fn build_with_config<S>(
+        stream_handle: StreamHandle<S>,
+        config: ServiceConfig,
+    ) -> Result<Handler>
     where
         S: AsyncReadExt + AsyncWriteExt + Send + 'static,
     {
-        let stream_api = StreamApi::new();
-        let config_id = generate_rand_id();
+        Self::with_connection(configure(stream_handle).await?, config)
+    }
 
-        let (packet_rx, stream_api) = stream_api.connect(stream_handle).await;
-        let stream_api = stream_api.configure(config_id).await?;
+    fn with_connection(connection: Connection, config: ServiceConfig) -> Result<Handler> {
+        let (packet_rx, stream_api) = connection;
 
         let (status_tx, status_rx) = tokio::sync::mpsc::unbounded_channel::<Status>();
-        let (msg_tx, msg_rx) = tokio::sync::mpsc::unbounded_channel::<TextMessage>();
+        let (msg_tx, msg_rx) = tokio::sync::mpsc::unbounded_channel::<QueuedMessage>();
 
         let (finished_tx, finished_rx) = oneshot::channel::<()>();
 
-        let state = Arc::new(RwLock::new(HandlerState::default()));
+        let state = Arc::new(RwLock::new(HandlerState {
+            outbox: Outbox::load(config.outbox)?,
+            nodes: NodeDb::load(config.nodedb)?,
+            store_forward: StoreForward::load(config.store_forward)?,
+            ..Default::default()
+        }));
 
         let cancel = CancellationToken::new();
+        let confirm = config.confirm;
 
         let handler = Handler {
             state: state.clone(),
             cancel: cancel.clone(),
             msg_tx,
+            confirm: confirm.clone(),
             status_rx,
             finished_rx,
         };
@@ -255,6 +576,11 @@ impl Service {
             status_tx,
             finished_tx,
             config_complete: false,
+            queue: OutgoingQueue::new(config.pacing),
+            in_flight: HashMap::new(),
+            waiters: HashMap::new(),
+            ack_timeout: confirm.ack_timeout,
+            store_forward_heartbeat: None,
         };
 
         tokio::spawn(service.start());
@@ -265,7 +591,7 @@ impl Service {
         let mut buffer_flushed = false;
         let mut packet_count = 0;
         let mut hearthbeat_counter = 0;
-        // let mut msg_queue = VecDeque::new();
+        let mut tick = tokio::time::interval(Duration::from_millis(500));
         let mut ret = Ok(());
 
         check!(self.status_tx.send(Status::Heartbeat(0)));
@@ -286,16 +612,14 @@ impl Service {
                         error!("Error processing packet: {:?} : {}", from_radio, error);
                     }
                 }
-                /*
-                msg = self.msg_rx.recv() => {
-                    let Some(msg) = msg else {
+                queued = self.msg_rx.recv() => {
+                    let Some(queued) = queued else {
                         ret = Err(anyhow!("Text message stream closed"));
                         break;
                     };
-                    msg_queue.push_back(msg);
+                    self.enqueue(queued);
                 }
-                */
-                _ = tokio::time::sleep(Duration::from_millis(500)) => {
+                _ = tick.tick() => {
                     hearthbeat_counter += 1;
 
                     // Each 500 ms
@@ -305,16 +629,19 @@ impl Service {
                         check!(self.status_tx.send(Status::Ready));
                     }
 
-                    // Each second
-                    if hearthbeat_counter % 2 == 0 {
-                        //if let Some(msg) = msg_queue.pop_front() {
-                        //    check!(self.process_send_text(msg.clone()).await);
-                        //}
+                    if buffer_flushed
+                        && let Some(queued) = self.queue.pop_ready(Instant::now())
+                    {
+                        check!(self.status_tx.send(Status::QueueDepth(self.queue.len())));
+                        check!(self.process_queued(queued).await);
                     }
+                    self.expire_waiters();
 
                     // Each 10 second
                     if hearthbeat_counter % 20 == 0 {
                         check!(self.status_tx.send(Status::Heartbeat(packet_count)));
+                        check!(w!(self.nodes).save_if_due());
+                        check!(self.send_store_forward_heartbeat().await);
                     }
 
                 }
@@ -325,32 +652,148 @@ impl Service {
         }
 
         self.packet_rx.close();
+        check!(w!(self.nodes).save());
         check!(self.stream_api.disconnect().await);
         check!(self.finished_tx.send(()));
 
         ret
     }
 
-    async fn process_send_text(&mut self, msg: TextMessage) -> Result<()> {
+    fn enqueue(&mut self, queued: QueuedMessage) {
+        self.queue.push(queued);
+        check!(self.status_tx.send(Status::QueueDepth(self.queue.len())));
+    }
+
+    async fn process_queued(&mut self, queued: QueuedMessage) -> Result<()> {
+        let packet_id = match queued.data {
+            Some(data) => self.send_data(&queued.msg, data).await?,
+            None => self.process_send_text(queued.msg).await?,
+        };
+        if let Some(outbox_id) = queued.outbox_id {
+            self.in_flight
+                .insert(packet_id, (outbox_id, Instant::now()));
+        }
+        if let Some(confirm) = queued.confirm {
+            self.waiters.insert(packet_id, (Instant::now(), confirm));
+        }
+        Ok(())
+    }
+
+    /// Confirmed messages without a final status after `ack_timeout` are
+    /// reported as still `Sent`, resent ones are left in the outbox for the
+    /// next time their destination is heard.
+    fn expire_waiters(&mut self) {
+        let ack_timeout = self.ack_timeout;
+        self.in_flight
+            .retain(|_, (_, sent)| sent.elapsed() < ack_timeout);
+        let expired: Vec<u32> = self
+            .waiters
+            .iter()
+            .filter(|(_, (sent, _))| sent.elapsed() >= ack_timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some((_, confirm)) = self.waiters.remove(&id) {
+                let _ = confirm.send(Sent);
+            }
+        }
+    }
+
+    async fn process_send_text(&mut self, msg: TextMessage) -> Result<u32> {
+        let data = Data {
+            portnum: PortNum::TextMessageApp as i32,
+            payload: msg.text.clone().into_bytes(),
+            ..Default::default()
+        };
+        let id = self.send_data(&msg, data).await?;
+        w!(self.messages).insert(id, msg);
+        self.status_tx.send(Status::NewMessage(id))?;
+
+        Ok(id)
+    }
+
+    /// Transmits `data` to the destination of `msg` with its priority. Requests
+    /// are not acknowledged, their response tells they arrived.
+    async fn send_data(&mut self, msg: &TextMessage, data: Data) -> Result<u32> {
         let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
-        let mut packet_router = Router::new(NodeId::new(from));
+        let id = generate_rand_id();
+        let packet = MeshPacket {
+            from,
+            to: msg.to,
+            id,
+            channel: msg.channel,
+            hop_limit: DEFAULT_HOP_LIMIT,
+            hop_start: DEFAULT_HOP_LIMIT,
+            want_ack: !data.want_response,
+            priority: msg.priority.mesh_priority() as i32,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+            ..Default::default()
+        };
         self.stream_api
-            .send_text(
-                &mut packet_router,
-                msg.text.clone(),
-                PacketDestination::Node(NodeId::new(msg.to)),
-                true,
-                MeshChannel::new(0).unwrap(),
-            )
+            .send_to_radio_packet(Some(to_radio::PayloadVariant::Packet(packet)))
             .await?;
-        let id = packet_router.last_sent().unwrap().id;
-        w!(self.messages).insert(id, msg);
-        self.status_tx.send(Status::NewMessage(id))?;
+        Ok(id)
+    }
 
+    /// Queues a Store & Forward message, behind the other traffic.
+    async fn queue_store_forward(&mut self, to: u32, message: StoreAndForward) -> Result<()> {
+        let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+        let mut msg = TextMessage::sent(from, to, String::new());
+        msg.priority = MessagePriority::Background;
+        self.enqueue(QueuedMessage {
+            msg,
+            data: Some(Data {
+                portnum: PortNum::StoreForwardApp as i32,
+                payload: message.encode_to_vec(),
+                ..Default::default()
+            }),
+            outbox_id: None,
+            confirm: None,
+        });
         Ok(())
     }
 
+    async fn send_store_forward_heartbeat(&mut self) -> Result<()> {
+        let Some(interval) = r!(self.store_forward).heartbeat_interval() else {
+            return Ok(());
+        };
+        if self
+            .store_forward_heartbeat
+            .is_some_and(|last| last.elapsed() < interval)
+        {
+            return Ok(());
+        }
+        self.store_forward_heartbeat = Some(Instant::now());
+        let heartbeat = r!(self.store_forward).heartbeat();
+        self.queue_store_forward(0xffffffff, heartbeat).await
+    }
+
+    async fn retry_pending(&mut self, node_id: u32) -> Result<()> {
+        let due = w!(self.outbox).due_for(node_id)?;
+        for pending in due {
+            let from = r!(self.my_node_info).as_ref().unwrap().my_node_num;
+            let mut msg = TextMessage::sent(from, pending.to, pending.text);
+            msg.channel = pending.channel;
+            msg.origin = pending.origin;
+            self.enqueue(QueuedMessage {
+                msg,
+                data: None,
+                outbox_id: Some(pending.id),
+                confirm: None,
+            });
+        }
+        Ok(())
+    }
+
+    async fn node_heard(&mut self, node_id: u32, channel: u32) -> Result<()> {
+        self.status_tx.send(Status::NodeHeard(node_id, channel))?;
+        self.retry_pending(node_id).await
+    }
+
     async fn process_from_radio(&mut self, from_radio: FromRadio) -> Result<()> {
+        if w!(self.channels).add_from_radio(&from_radio) {
+            return Ok(());
+        }
         let Some(payload) = from_radio.payload_variant else {
             bail!("No payload");
         };
@@ -361,13 +804,47 @@ impl Service {
             }
             // Local for the data in NodeDB
             from_radio::PayloadVariant::NodeInfo(node_info) if node_info.user.is_some() => {
-                w!(self.nodes).insert(node_info.num, node_info.user.unwrap());
+                {
+                    let mut state = self.state.write().await;
+                    if let Some(me) = state.my_node_info.as_ref().map(|info| info.my_node_num) {
+                        state.topology.add_node_info(me, &node_info);
+                    }
+                }
+                if let Some(position) = node_info
+                    .position
+                    .as_ref()
+                    .and_then(|p| NodePosition::from_position(p, node_info.last_heard as i64))
+                {
+                    self.state
+                        .write()
+                        .await
+                        .add_position(node_info.num, position);
+                }
+                w!(self.nodes).add_node_info(&node_info);
+                // NodeDB dump at boot does not mean the node is around
+                if self.config_complete {
+                    self.node_heard(node_info.num, node_info.channel).await?;
+                }
             }
             from_radio::PayloadVariant::ConfigCompleteId(_) => {
                 self.config_complete = true;
             }
             // Mesh packet loaded
             from_radio::PayloadVariant::Packet(mesh_packet) => {
+                {
+                    let mut state = self.state.write().await;
+                    let time = rx_time(&mesh_packet);
+                    if let Some(me) = state.my_node_info.as_ref().map(|info| info.my_node_num) {
+                        state.topology.add_packet(me, &mesh_packet, time);
+                        if mesh_packet.from != me {
+                            state.nodes.add_packet(&mesh_packet, time);
+                        }
+                    }
+                }
+                if self.is_remote(mesh_packet.from).await {
+                    self.node_heard(mesh_packet.from, mesh_packet.channel)
+                        .await?;
+                }
                 if let Some(mesh_packet::PayloadVariant::Decoded(ref data)) =
                     mesh_packet.payload_variant
                 {
@@ -379,6 +856,19 @@ impl Service {
                             self.handle_textmessage(&mesh_packet, data).await?
                         }
                         Ok(PortNum::RoutingApp) => self.handle_routing(&mesh_packet, &data).await?,
+                        Ok(PortNum::PositionApp) => {
+                            self.handle_position(&mesh_packet, data).await?
+                        }
+                        Ok(PortNum::TelemetryApp) => {
+                            self.handle_telemetry(&mesh_packet, data).await?
+                        }
+                        Ok(PortNum::TracerouteApp) => {
+                            self.handle_traceroute(&mesh_packet, data).await?
+                        }
+                        Ok(PortNum::AdminApp) => self.handle_admin(&mesh_packet, data).await?,
+                        Ok(PortNum::StoreForwardApp) => {
+                            self.handle_store_forward(&mesh_packet, data).await?
+                        }
                         _ => {}
                     }
                 }
@@ -388,24 +878,127 @@ impl Service {
         Ok(())
     }
 
+    async fn is_remote(&self, node_id: u32) -> bool {
+        r!(self.my_node_info)
+            .as_ref()
+            .is_some_and(|info| info.my_node_num != node_id)
+    }
+
     async fn handle_nodeinfo(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
         let user = User::decode(data.payload.as_slice())?;
-        w!(self.nodes).insert(mesh_packet.from, user);
+        w!(self.nodes).set_user(mesh_packet.from, user);
         Ok(())
     }
 
     async fn handle_textmessage(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
-        let msg = String::from_utf8(data.payload.clone())?;
-        w!(self.messages).insert(
-            mesh_packet.id,
-            TextMessage::recieved(mesh_packet.from, mesh_packet.to, msg),
-        );
+        let text = String::from_utf8(data.payload.clone())?;
+        if mesh_packet.to == 0xffffffff
+            && mesh_packet.channel == 0
+            && self.is_remote(mesh_packet.from).await
+        {
+            w!(self.store_forward).record(mesh_packet.from, text.clone(), rx_time(mesh_packet))?;
+        }
+        let mut msg = TextMessage::recieved(mesh_packet.from, mesh_packet.to, text);
+        msg.channel = mesh_packet.channel;
+        w!(self.messages).insert(mesh_packet.id, msg);
         self.status_tx.send(Status::NewMessage(mesh_packet.id))?;
 
         Ok(())
     }
 
-    async fn handle_routing(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+    async fn handle_position(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        let position = Position::decode(data.payload.as_slice())?;
+        let Some(position) = NodePosition::from_position(&position, rx_time(mesh_packet)) else {
+            return Ok(());
+        };
+        if self
+            .state
+            .write()
+            .await
+            .add_position(mesh_packet.from, position)
+        {
+            self.status_tx.send(Status::NewPosition(mesh_packet.from))?;
+        }
+        Ok(())
+    }
+
+    async fn handle_telemetry(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        let telemetry = Telemetry::decode(data.payload.as_slice())?;
+        let Some(sample) = NodeTelemetry::from_telemetry(&telemetry, rx_time(mesh_packet)) else {
+            return Ok(());
+        };
+        self.state
+            .write()
+            .await
+            .add_telemetry(mesh_packet.from, sample);
+        self.status_tx
+            .send(Status::NewTelemetry(mesh_packet.from))?;
+        Ok(())
+    }
+
+    async fn handle_traceroute(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        // Only the responses to our requests carry the full route
+        if data.request_id == 0 || self.is_remote(mesh_packet.to).await {
+            return Ok(());
+        }
+        let route = RouteDiscovery::decode(data.payload.as_slice())?;
+        let traceroute = Traceroute::from_route_discovery(
+            mesh_packet.to,
+            mesh_packet.from,
+            &route,
+            rx_time(mesh_packet),
+        );
+        w!(self.traceroutes).insert(mesh_packet.from, traceroute.clone());
+        self.status_tx.send(Status::Traceroute(traceroute))?;
+        Ok(())
+    }
+
+    async fn handle_admin(&self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        // Only the responses to our requests
+        if data.request_id == 0 || self.is_remote(mesh_packet.to).await {
+            return Ok(());
+        }
+        let admin_message = AdminMessage::decode(data.payload.as_slice())?;
+        if !admin_message.session_passkey.is_empty() {
+            w!(self.admin_sessions).set(mesh_packet.from, admin_message.session_passkey.clone());
+        }
+        if let Some(response) = AdminResponse::from_admin_message(&admin_message) {
+            self.status_tx
+                .send(Status::Admin(mesh_packet.from, response))?;
+        }
+        Ok(())
+    }
+
+    async fn handle_store_forward(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
+        // Clients send their requests to the server, heartbeats of other servers are broadcast
+        if !r!(self.store_forward).enabled() || self.is_remote(mesh_packet.to).await {
+            return Ok(());
+        }
+        let request = StoreAndForward::decode(data.payload.as_slice())?;
+        let responses = {
+            let mut state = self.state.write().await;
+            let HandlerState {
+                store_forward,
+                nodes,
+                ..
+            } = &mut *state;
+            store_forward.handle_request(
+                mesh_packet.from,
+                &request,
+                rx_time(mesh_packet),
+                |node_id| match nodes.user(node_id) {
+                    Some(user) => user.short_name.clone(),
+                    None => format!("!{:08x}", node_id),
+                },
+            )?
+        };
+        for response in responses {
+            self.queue_store_forward(mesh_packet.from, response).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_routing(&mut self, mesh_packet: &MeshPacket, data: &Data) -> Result<()> {
         let Routing { variant } = Routing::decode(data.payload.as_slice())?;
         let Some(routing::Variant::ErrorReason(routing_error)) = variant else {
             return Ok(());
@@ -421,14 +1014,568 @@ impl Service {
             status = Some(ExplicitAck);
         }
 
-        if let Some(msg) = w!(self.messages).get_mut(&data.request_id)
-            && let Some(status) = status
-        {
-            msg.status = status;
-            self.status_tx
-                .send(Status::UpdatedMessage(data.request_id))?;
+        let Some(status) = status else {
+            return Ok(());
+        };
+        let msg = {
+            let mut state = self.state.write().await;
+            state.messages.get_mut(&data.request_id).map(|msg| {
+                msg.status = status.clone();
+                msg.clone()
+            })
+        };
+        let Some(msg) = msg else {
+            // Requests of other applications are not kept as messages
+            if matches!(status, RoutingError(_) | ExplicitAck)
+                && let Some((_, confirm)) = self.waiters.remove(&data.request_id)
+            {
+                let _ = confirm.send(status);
+            }
+            return Ok(());
+        };
+        self.status_tx
+            .send(Status::UpdatedMessage(data.request_id))?;
+
+        let is_final = match msg.status {
+            RoutingError(_) | ExplicitAck => true,
+            ImplicitAck => msg.to == 0xffffffff,
+            _ => false,
+        };
+        // Confirmed sends are retried by their sender, which moves them to the
+        // outbox once the last attempt fails
+        let mut confirmed = false;
+        if is_final && let Some((_, confirm)) = self.waiters.remove(&data.request_id) {
+            let _ = confirm.send(msg.status.clone());
+            confirmed = true;
         }
 
+        self.track_delivery(data.request_id, &msg, is_final, confirmed)
+            .await
+    }
+
+    /// Direct messages that fail are moved to the outbox, and removed from it
+    /// once the destination acknowledges them.
+    async fn track_delivery(
+        &mut self,
+        packet_id: u32,
+        msg: &TextMessage,
+        is_final: bool,
+        confirmed: bool,
+    ) -> Result<()> {
+        // Resent messages stay in the outbox until the next time the node is heard
+        let outbox_id = if is_final {
+            self.in_flight.remove(&packet_id).map(|(id, _)| id)
+        } else {
+            None
+        };
+        if msg.to == 0xffffffff || self.is_remote(msg.from).await {
+            return Ok(());
+        }
+        match msg.status {
+            RoutingError(_) if outbox_id.is_none() && !confirmed => {
+                w!(self.outbox).push(msg)?;
+            }
+            ExplicitAck => {
+                if let Some(id) = outbox_id {
+                    w!(self.outbox).delivered(id)?;
+                }
+            }
+            _ => {}
+        }
         Ok(())
     }
 }
+
+#[tokio::test]
+async fn test_boot_and_send_text_confirmed() -> Result<()> {
+    use crate::mesh::sim::radio::{ME, PEER, boot_handler, routing_packet};
+
+    let (handler, mut radio) = boot_handler(ServiceConfig::default()).await?;
+    assert_eq!(
+        handler.state.read().await.get_node_id_by_short_name("peer"),
+        Some(PEER)
+    );
+
+    let (delivery, packet) = tokio::join!(
+        handler.send_text_confirmed("hello", "peer", 0, MessagePriority::Normal),
+        async {
+            let packet = radio.next_packet().await?;
+            radio.send_packet(routing_packet(PEER, ME, packet.id, routing::Error::None));
+            anyhow::Ok(packet)
+        }
+    );
+    let packet = packet?;
+    assert_eq!(packet.from, ME);
+    assert_eq!(packet.to, PEER);
+    assert_eq!(delivery?, DeliveryResult::Delivered);
+
+    let msg = handler.state.read().await.msg(packet.id).await.unwrap();
+    assert_eq!(msg.text, "hello");
+    assert!(matches!(msg.status, ExplicitAck));
+
+    handler.finish().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_send_text_confirmed_fails() -> Result<()> {
+    use crate::mesh::sim::radio::{ME, PEER, boot_handler, routing_packet};
+
+    let outbox = std::env::temp_dir().join(format!("mbbs-nak-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&outbox);
+    let config = ServiceConfig {
+        outbox: OutboxConfig {
+            path: outbox.clone(),
+            ..Default::default()
+        },
+        pacing: PacingConfig {
+            min_spacing: Duration::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (handler, mut radio) = boot_handler(config).await?;
+
+    // The radio gives up every attempt, the same text sent twice is kept twice
+    for pending in [1, 2] {
+        let send = handler.send_text_confirmed("ok", "peer", 0, MessagePriority::Normal);
+        let (delivery, attempts) = tokio::join!(send, async {
+            for _ in 0..=handler.confirm.retries {
+                let packet = radio.next_packet().await?;
+                radio.send_packet(routing_packet(
+                    ME,
+                    ME,
+                    packet.id,
+                    routing::Error::MaxRetransmit,
+                ));
+            }
+            anyhow::Ok(handler.confirm.retries + 1)
+        });
+        assert_eq!(attempts?, 3);
+        assert_eq!(
+            delivery?,
+            DeliveryResult::Failed(routing::Error::MaxRetransmit)
+        );
+        // Only the last attempt is queued for later delivery
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            handler.state.read().await.outbox.pending_for(PEER).len(),
+            pending
+        );
+    }
+
+    handler.finish().await;
+    let _ = std::fs::remove_file(outbox);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receive_text() -> Result<()> {
+    use crate::mesh::sim::radio::{ME, PEER, boot_handler, text_packet, wait_for_status};
+
+    let (mut handler, radio) = boot_handler(ServiceConfig::default()).await?;
+
+    radio.send_packet(text_packet(7, PEER, ME, "hi bbs"));
+    wait_for_status(&mut handler, |s| *s == Status::NodeHeard(PEER, 0)).await?;
+    wait_for_status(&mut handler, |s| *s == Status::NewMessage(7)).await?;
+
+    let state = handler.state.read().await;
+    let msg = state.msg(7).await.unwrap();
+    assert_eq!((msg.from, msg.to), (PEER, ME));
+    assert_eq!(msg.text, "hi bbs");
+    drop(state);
+
+    handler.finish().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_secondary_channel() -> Result<()> {
+    use crate::mesh::sim::radio::{
+        ME, PEER, boot_handler, routing_packet, text_packet, wait_for_status,
+    };
+    use meshtastic::protobufs::{Channel, ChannelSettings, channel};
+
+    let outbox = std::env::temp_dir().join(format!("mbbs-channel-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&outbox);
+    let config = ServiceConfig {
+        outbox: OutboxConfig {
+            path: outbox.clone(),
+            retry_interval: Duration::ZERO,
+            ..Default::default()
+        },
+        pacing: PacingConfig {
+            min_spacing: Duration::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (mut handler, mut radio) = boot_handler(config).await?;
+
+    radio.send(from_radio::PayloadVariant::Channel(Channel {
+        index: 1,
+        settings: Some(ChannelSettings {
+            name: "Private".into(),
+            ..Default::default()
+        }),
+        role: channel::Role::Secondary as i32,
+    }));
+    radio.send_packet(MeshPacket {
+        channel: 1,
+        ..text_packet(7, PEER, 0xffffffff, "hi private")
+    });
+    wait_for_status(&mut handler, |s| *s == Status::NewMessage(7)).await?;
+    {
+        let state = handler.state.read().await;
+        let msg = state.msg(7).await.unwrap();
+        assert_eq!(msg.channel, 1);
+        assert!(state.format_msg(&msg).starts_with("💬 [Private] "));
+    }
+
+    handler
+        .send_text("hi back", Destination::Broadcast, 1)
+        .await?;
+    let packet = radio.next_packet().await?;
+    assert_eq!((packet.to, packet.channel), (0xffffffff, 1));
+
+    // A failed direct message is resent in its channel
+    handler.send_text("dm", Destination::Node(PEER), 1).await?;
+    let packet = radio.next_packet().await?;
+    radio.send_packet(routing_packet(
+        ME,
+        ME,
+        packet.id,
+        routing::Error::MaxRetransmit,
+    ));
+    for _ in 0..50 {
+        if !handler
+            .state
+            .read()
+            .await
+            .outbox
+            .pending_for(PEER)
+            .is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        handler.state.read().await.outbox.pending_for(PEER)[0].channel,
+        1
+    );
+    radio.send_packet(text_packet(8, PEER, ME, "back in range"));
+    let packet = radio.next_packet().await?;
+    assert_eq!((packet.to, packet.channel), (PEER, 1));
+
+    handler.finish().await;
+    let _ = std::fs::remove_file(outbox);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_admin_session() -> Result<()> {
+    use crate::mesh::sim::radio::{ME, PEER, boot_handler, data_packet, routing_packet};
+    use meshtastic::protobufs::admin_message;
+
+    let config = ServiceConfig {
+        pacing: PacingConfig {
+            min_spacing: Duration::ZERO,
+            ..Default::default()
+        },
+        confirm: ConfirmConfig {
+            ack_timeout: Duration::from_secs(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (mut handler, mut radio) = boot_handler(config).await?;
+
+    let owner = User {
+        long_name: "Hill relay".into(),
+        short_name: "hill".into(),
+        ..Default::default()
+    };
+    let admin_message = |packet: &MeshPacket| -> Result<AdminMessage> {
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+            bail!("Not decoded");
+        };
+        assert_eq!(data.portnum, PortNum::AdminApp as i32);
+        Ok(AdminMessage::decode(data.payload.as_slice())?)
+    };
+    let request = AdminRequest::SetOwner(owner.clone());
+    let (response, requests) = tokio::join!(
+        handler.admin(PEER, request, Duration::from_secs(5)),
+        async {
+            // No passkey yet, the owner is asked first
+            let get = radio.next_packet().await?;
+            let response = AdminMessage {
+                session_passkey: vec![9, 9],
+                payload_variant: Some(admin_message::PayloadVariant::GetOwnerResponse(
+                    User::default(),
+                )),
+            };
+            radio.send_packet(data_packet(
+                100,
+                PEER,
+                ME,
+                Data {
+                    portnum: PortNum::AdminApp as i32,
+                    payload: response.encode_to_vec(),
+                    request_id: get.id,
+                    ..Default::default()
+                },
+            ));
+            let set = radio.next_packet().await?;
+            radio.send_packet(routing_packet(PEER, ME, set.id, routing::Error::None));
+            anyhow::Ok((admin_message(&get)?, admin_message(&set)?))
+        }
+    );
+    assert_eq!(response?, None);
+    let (get, set) = requests?;
+    assert_eq!(
+        get.payload_variant,
+        Some(admin_message::PayloadVariant::GetOwnerRequest(true))
+    );
+    assert_eq!(set.session_passkey, vec![9, 9]);
+    assert_eq!(
+        set.payload_variant,
+        Some(admin_message::PayloadVariant::SetOwner(owner))
+    );
+
+    // Requests the node never acknowledges fail
+    let response = handler
+        .admin(PEER, AdminRequest::Reboot(5), Duration::from_secs(5))
+        .await;
+    assert!(
+        response
+            .unwrap_err()
+            .to_string()
+            .contains("No acknowledgement")
+    );
+    radio.next_packet().await?;
+
+    handler.finish().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_store_forward_history() -> Result<()> {
+    use crate::mesh::sim::radio::{ME, PEER, data_packet, fake_radio, text_packet};
+    use meshtastic::protobufs::store_and_forward::{self, RequestResponse};
+    const CLIENT: u32 = 0x33;
+
+    let path = std::env::temp_dir().join(format!("mbbs-sf-service-{}.json", std::process::id()));
+    let config = ServiceConfig {
+        pacing: PacingConfig {
+            min_spacing: Duration::ZERO,
+            ..Default::default()
+        },
+        store_forward: StoreForwardConfig {
+            enabled: true,
+            path: path.clone(),
+            heartbeat_interval: Duration::ZERO,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (stream, mut radio) = fake_radio();
+    let mut handler = Service::build_with_config(stream, config).await?;
+    radio
+        .boot(ME, &[(ME, "me"), (PEER, "peer"), (CLIENT, "cli")])
+        .await?;
+    handler.wait_for_boot_ready(5).await?;
+
+    radio.send_packet(text_packet(7, PEER, 0xffffffff, "anyone around?"));
+    let request = StoreAndForward {
+        rr: RequestResponse::ClientHistory as i32,
+        variant: Some(store_and_forward::Variant::History(
+            store_and_forward::History::default(),
+        )),
+    };
+    radio.send_packet(data_packet(
+        8,
+        CLIENT,
+        ME,
+        Data {
+            portnum: PortNum::StoreForwardApp as i32,
+            payload: request.encode_to_vec(),
+            ..Default::default()
+        },
+    ));
+
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let packet = radio.next_packet().await?;
+        assert_eq!(packet.to, CLIENT);
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = packet.payload_variant else {
+            bail!("Not decoded");
+        };
+        responses.push(StoreAndForward::decode(data.payload.as_slice())?);
+    }
+    assert_eq!(responses[0].rr, RequestResponse::RouterHistory as i32);
+    assert_eq!(
+        responses[1],
+        StoreAndForward {
+            rr: RequestResponse::RouterTextBroadcast as i32,
+            variant: Some(store_and_forward::Variant::Text(
+                b"peer: anyone around?".to_vec()
+            )),
+        }
+    );
+
+    handler.finish().await;
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receive_position() -> Result<()> {
+    use crate::mesh::sim::radio::{PEER, boot_handler, data_packet, wait_for_status};
+
+    let (mut handler, radio) = boot_handler(ServiceConfig::default()).await?;
+
+    let position = Position {
+        latitude_i: Some(454_642_100),
+        longitude_i: Some(91_895_100),
+        altitude: Some(122),
+        time: 1_700_000_000,
+        ..Default::default()
+    };
+    for id in [1, 2] {
+        radio.send_packet(data_packet(
+            id,
+            PEER,
+            0xffffffff,
+            Data {
+                portnum: PortNum::PositionApp as i32,
+                payload: position.encode_to_vec(),
+                ..Default::default()
+            },
+        ));
+    }
+    wait_for_status(&mut handler, |s| *s == Status::NewPosition(PEER)).await?;
+    // Let the repeated report be processed
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let state = handler.state.read().await;
+    assert_eq!(state.positions[&PEER].len(), 1);
+    let last = state.last_position(PEER).unwrap();
+    assert!((last.latitude - 45.46421).abs() < 1e-6);
+    assert_eq!(
+        last.describe(1_700_000_300),
+        "45.46421,9.18951 122m, 5m ago"
+    );
+    drop(state);
+
+    handler.finish().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receive_telemetry() -> Result<()> {
+    use crate::mesh::sim::radio::{PEER, boot_handler, data_packet, wait_for_status};
+    use meshtastic::protobufs::{DeviceMetrics, EnvironmentMetrics, telemetry};
+
+    let (mut handler, radio) = boot_handler(ServiceConfig::default()).await?;
+
+    let samples = [
+        telemetry::Variant::DeviceMetrics(DeviceMetrics {
+            battery_level: Some(87),
+            channel_utilization: Some(12.5),
+            ..Default::default()
+        }),
+        telemetry::Variant::EnvironmentMetrics(EnvironmentMetrics {
+            temperature: Some(21.5),
+            ..Default::default()
+        }),
+    ];
+    for (id, variant) in samples.into_iter().enumerate() {
+        let telemetry = Telemetry {
+            time: 1_700_000_000 + id as u32,
+            variant: Some(variant),
+        };
+        radio.send_packet(data_packet(
+            id as u32 + 1,
+            PEER,
+            0xffffffff,
+            Data {
+                portnum: PortNum::TelemetryApp as i32,
+                payload: telemetry.encode_to_vec(),
+                ..Default::default()
+            },
+        ));
+    }
+    for _ in 0..2 {
+        wait_for_status(&mut handler, |s| *s == Status::NewTelemetry(PEER)).await?;
+    }
+
+    let state = handler.state.read().await;
+    assert_eq!(state.telemetry[&PEER].len(), 2);
+    let latest = state.latest_telemetry(PEER).unwrap();
+    assert_eq!(latest.time, 1_700_000_001);
+    assert_eq!(latest.battery_level, Some(87));
+    assert_eq!(latest.temperature, Some(21.5));
+    drop(state);
+
+    handler.finish().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_traceroute() -> Result<()> {
+    use crate::mesh::sim::radio::{ME, PEER, data_packet, fake_radio, wait_for_status};
+
+    const RELAY: u32 = 0x33;
+
+    let (stream, mut radio) = fake_radio();
+    let mut handler = Service::build_with_config(stream, ServiceConfig::default()).await?;
+    radio
+        .boot(ME, &[(ME, "me"), (PEER, "peer"), (RELAY, "rly")])
+        .await?;
+    handler.wait_for_boot_ready(5).await?;
+
+    handler.send_traceroute("peer").await?;
+    let request = radio.next_packet().await?;
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &request.payload_variant else {
+        bail!("Not decoded");
+    };
+    assert_eq!(request.to, PEER);
+    assert_eq!(data.portnum, PortNum::TracerouteApp as i32);
+    assert!(data.want_response && !request.want_ack);
+
+    let route = RouteDiscovery {
+        route: vec![RELAY],
+        snr_towards: vec![26, -14],
+        route_back: vec![RELAY],
+        snr_back: vec![-128, 16],
+    };
+    radio.send_packet(data_packet(
+        9,
+        PEER,
+        ME,
+        Data {
+            portnum: PortNum::TracerouteApp as i32,
+            payload: route.encode_to_vec(),
+            request_id: request.id,
+            ..Default::default()
+        },
+    ));
+    let status = wait_for_status(&mut handler, |s| matches!(s, Status::Traceroute(_))).await?;
+    let Status::Traceroute(traceroute) = status else {
+        bail!("Not a traceroute");
+    };
+    assert_eq!(traceroute.to, PEER);
+    assert_eq!(traceroute.hops(), 2);
+    let state = handler.state.read().await;
+    let name = |id| state.node_label(id);
+    assert_eq!(
+        traceroute.describe(name),
+        "me → rly 6.5dB → peer -3.5dB | back: peer → rly → me 4.0dB"
+    );
+    assert_eq!(state.traceroutes[&PEER], traceroute);
+    drop(state);
+
+    handler.finish().await;
+    Ok(())
+}
@@ -0,0 +1,4 @@
+//! Simulated radios to exercise `mesh::service` without hardware.
+pub mod network;
+pub mod radio;
+mod scenarios;
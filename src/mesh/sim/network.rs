@@ -0,0 +1,357 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use meshtastic::{
+    Message,
+    protobufs::{
+        Data, FromRadio, MeshPacket, PortNum, Routing, from_radio, mesh_packet, routing, to_radio,
+    },
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use super::radio::{fake_radio, routing_packet, text_packet};
+use crate::mesh::service::{Handler, Service, ServiceConfig};
+
+pub const BROADCAST: u32 = 0xffffffff;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Link {
+    pub latency: Duration,
+    // Probability of losing each packet, from 0.0 to 1.0
+    pub loss: f64,
+    pub snr: f32,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(20),
+            loss: 0.0,
+            snr: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    // Hop limit of the packets sent by the virtual nodes
+    pub hop_limit: u32,
+    // Time the gateway radio waits for an ACK before reporting `MaxRetransmit`
+    pub ack_timeout: Duration,
+    // Seed of the packet loss generator, runs with the same seed lose the same packets
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            hop_limit: 3,
+            ack_timeout: Duration::from_secs(1),
+            seed: 0x5eed,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Endpoint {
+    Gateway(UnboundedSender<FromRadio>),
+    Node(UnboundedSender<MeshPacket>),
+}
+
+struct Inner {
+    config: SimConfig,
+    names: HashMap<u32, String>,
+    endpoints: HashMap<u32, Endpoint>,
+    links: HashMap<(u32, u32), Link>,
+    // (node, from, id) of the packets each node already handled
+    seen: HashSet<(u32, u32, u32)>,
+    // Packet ids sent by the gateway that were acknowledged
+    acked: HashSet<u32>,
+    next_id: u32,
+    rng: u64,
+}
+
+impl Inner {
+    /// Uniform value in [0, 1) from a xorshift generator.
+    fn roll(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Virtual mesh of nodes joined by links with latency and loss. Packets are
+/// flooded like the firmware does: every node in range hears a transmission
+/// and rebroadcasts it while its hop limit allows. One node can be a gateway
+/// running `mesh::service` through a fake radio.
+#[derive(Clone)]
+pub struct SimNetwork {
+    inner: Arc<Mutex<Inner>>,
+}
+
+/// Virtual node driven by the test.
+pub struct SimNode {
+    pub id: u32,
+    inbox: UnboundedReceiver<MeshPacket>,
+    net: SimNetwork,
+}
+
+fn link_key(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+/// Request id of a routing ACK, `None` for any other packet.
+fn acked_request_id(packet: &MeshPacket) -> Option<u32> {
+    let Some(mesh_packet::PayloadVariant::Decoded(data)) = &packet.payload_variant else {
+        return None;
+    };
+    if data.portnum != PortNum::RoutingApp as i32 {
+        return None;
+    }
+    let Routing { variant } = Routing::decode(data.payload.as_slice()).ok()?;
+    match variant {
+        Some(routing::Variant::ErrorReason(error)) if error == routing::Error::None as i32 => {
+            Some(data.request_id)
+        }
+        _ => None,
+    }
+}
+
+fn is_routing(packet: &MeshPacket) -> bool {
+    matches!(
+        &packet.payload_variant,
+        Some(mesh_packet::PayloadVariant::Decoded(Data { portnum, .. }))
+            if *portnum == PortNum::RoutingApp as i32
+    )
+}
+
+impl SimNetwork {
+    pub fn new(config: SimConfig) -> Self {
+        let rng = config.seed.max(1);
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                config,
+                names: HashMap::new(),
+                endpoints: HashMap::new(),
+                links: HashMap::new(),
+                seen: HashSet::new(),
+                acked: HashSet::new(),
+                next_id: 1000,
+                rng,
+            })),
+        }
+    }
+
+    pub fn add_node(&self, id: u32, short_name: &str) -> SimNode {
+        let (inbox_tx, inbox) = unbounded_channel();
+        let mut inner = self.inner.lock().unwrap();
+        inner.names.insert(id, short_name.to_string());
+        inner.endpoints.insert(id, Endpoint::Node(inbox_tx));
+        SimNode {
+            id,
+            inbox,
+            net: self.clone(),
+        }
+    }
+
+    pub fn link(&self, a: u32, b: u32, link: Link) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.links.insert(link_key(a, b), link);
+    }
+
+    pub fn unlink(&self, a: u32, b: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.links.remove(&link_key(a, b));
+    }
+
+    /// Runs `mesh::service` on node `id` through a fake radio whose NodeDB
+    /// holds every node added so far, and waits until it is ready.
+    pub async fn start_gateway(
+        &self,
+        id: u32,
+        short_name: &str,
+        config: ServiceConfig,
+    ) -> Result<Handler> {
+        let (stream, mut radio) = fake_radio();
+        let mut handler = Service::build_with_config(stream, config).await?;
+
+        let nodes: Vec<(u32, String)> = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.names.insert(id, short_name.to_string());
+            inner
+                .endpoints
+                .insert(id, Endpoint::Gateway(radio.injector()));
+            inner
+                .names
+                .iter()
+                .map(|(id, name)| (*id, name.clone()))
+                .collect()
+        };
+        let nodes: Vec<(u32, &str)> = nodes.iter().map(|(id, n)| (*id, n.as_str())).collect();
+        radio.boot(id, &nodes).await?;
+
+        let net = self.clone();
+        tokio::spawn(async move {
+            while let Some(to_radio) = radio.recv().await {
+                if let Some(to_radio::PayloadVariant::Packet(packet)) = to_radio.payload_variant {
+                    net.gateway_transmit(id, packet);
+                }
+            }
+        });
+
+        handler.wait_for_boot_ready(5).await?;
+        Ok(handler)
+    }
+
+    fn next_id(&self) -> u32 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        inner.next_id
+    }
+
+    fn hop_limit(&self) -> u32 {
+        self.inner.lock().unwrap().config.hop_limit
+    }
+
+    /// Transmits a packet from the gateway, reporting `MaxRetransmit` to it
+    /// when a direct message is not acknowledged in time.
+    fn gateway_transmit(&self, gateway: u32, packet: MeshPacket) {
+        if packet.to != BROADCAST && packet.want_ack {
+            let net = self.clone();
+            let request_id = packet.id;
+            let ack_timeout = self.inner.lock().unwrap().config.ack_timeout;
+            tokio::spawn(async move {
+                tokio::time::sleep(ack_timeout).await;
+                net.check_ack(gateway, request_id);
+            });
+        }
+        self.transmit(gateway, packet);
+    }
+
+    fn check_ack(&self, gateway: u32, request_id: u32) {
+        let inner = self.inner.lock().unwrap();
+        if inner.acked.contains(&request_id) {
+            return;
+        }
+        if let Some(Endpoint::Gateway(radio_tx)) = inner.endpoints.get(&gateway) {
+            let error = routing_packet(gateway, gateway, request_id, routing::Error::MaxRetransmit);
+            let _ = radio_tx.send(FromRadio {
+                payload_variant: Some(from_radio::PayloadVariant::Packet(error)),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Sends `packet` from `sender` to every node in range.
+    fn transmit(&self, sender: u32, packet: MeshPacket) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.seen.insert((sender, packet.from, packet.id));
+        let neighbours: Vec<(u32, Link)> = inner
+            .links
+            .iter()
+            .filter_map(|(&(a, b), link)| {
+                if a == sender {
+                    Some((b, *link))
+                } else if b == sender {
+                    Some((a, *link))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for (neighbour, link) in neighbours {
+            if inner.roll() < link.loss {
+                continue;
+            }
+            let net = self.clone();
+            let packet = packet.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(link.latency).await;
+                net.receive(neighbour, link, packet);
+            });
+        }
+    }
+
+    fn receive(&self, node: u32, link: Link, mut packet: MeshPacket) {
+        let mut ack = None;
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.seen.insert((node, packet.from, packet.id)) {
+                return;
+            }
+            packet.rx_snr = link.snr;
+            packet.rx_rssi = -80;
+
+            if packet.to == node || packet.to == BROADCAST {
+                match inner.endpoints.get(&node).cloned() {
+                    Some(Endpoint::Gateway(radio_tx)) => {
+                        if let Some(request_id) = acked_request_id(&packet) {
+                            inner.acked.insert(request_id);
+                        }
+                        let _ = radio_tx.send(FromRadio {
+                            payload_variant: Some(from_radio::PayloadVariant::Packet(
+                                packet.clone(),
+                            )),
+                            ..Default::default()
+                        });
+                    }
+                    Some(Endpoint::Node(inbox_tx)) => {
+                        if packet.to == node && packet.want_ack && !is_routing(&packet) {
+                            ack = Some(routing_packet(
+                                node,
+                                packet.from,
+                                packet.id,
+                                routing::Error::None,
+                            ));
+                        }
+                        let _ = inbox_tx.send(packet.clone());
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        if let Some(mut ack) = ack {
+            ack.id = self.next_id();
+            ack.hop_limit = self.hop_limit();
+            ack.hop_start = ack.hop_limit;
+            self.transmit(node, ack);
+        }
+        if packet.to != node && packet.hop_limit > 0 {
+            packet.hop_limit -= 1;
+            self.transmit(node, packet);
+        }
+    }
+}
+
+impl SimNode {
+    /// Transmits a text message, asking for an ACK when it is a direct message.
+    pub fn send_text(&self, to: u32, text: &str) -> u32 {
+        let mut packet = text_packet(self.net.next_id(), self.id, to, text);
+        packet.want_ack = to != BROADCAST;
+        packet.hop_limit = self.net.hop_limit();
+        packet.hop_start = packet.hop_limit;
+        let id = packet.id;
+        self.net.transmit(self.id, packet);
+        id
+    }
+
+    /// Next text message received by the node, with its sender.
+    pub async fn next_text(&mut self) -> Result<(u32, String)> {
+        loop {
+            let packet = tokio::time::timeout(Duration::from_secs(10), self.inbox.recv())
+                .await?
+                .ok_or_else(|| anyhow!("Network dropped"))?;
+            if let Some(mesh_packet::PayloadVariant::Decoded(data)) = packet.payload_variant
+                && data.portnum == PortNum::TextMessageApp as i32
+            {
+                return Ok((packet.from, String::from_utf8(data.payload)?));
+            }
+        }
+    }
+}
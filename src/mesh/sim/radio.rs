@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use meshtastic::{
+    Message,
+    api::StreamHandle,
+    protobufs::{
+        Data, FromRadio, MeshPacket, MyNodeInfo, NodeInfo, PortNum, Routing, ToRadio, User,
+        from_radio, mesh_packet, routing, to_radio,
+    },
+};
+use tokio::{
+    io::DuplexStream,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+
+use crate::mesh::{
+    service::{Handler, Service, ServiceConfig, Status},
+    utils::duplex_radio,
+};
+
+// Nodes booted by `boot_handler`
+pub const ME: u32 = 0x11;
+pub const PEER: u32 = 0x22;
+
+/// Radio side of a `tokio::io::duplex` pair. Scripted `FromRadio` packets are
+/// written to the client, and the `ToRadio` packets written by the client are
+/// recorded.
+pub struct FakeRadio {
+    from_radio_tx: UnboundedSender<FromRadio>,
+    to_radio_rx: UnboundedReceiver<ToRadio>,
+}
+
+/// Creates the client stream to pass to `Service::build` and the radio
+/// attached to its other end.
+pub fn fake_radio() -> (StreamHandle<DuplexStream>, FakeRadio) {
+    let (stream, from_radio_tx, to_radio_rx) = duplex_radio();
+    (
+        stream,
+        FakeRadio {
+            from_radio_tx,
+            to_radio_rx,
+        },
+    )
+}
+
+/// Service attached to a fake radio with `ME` and `PEER` in its NodeDB,
+/// ready to send.
+pub async fn boot_handler(config: ServiceConfig) -> Result<(Handler, FakeRadio)> {
+    let (stream, mut radio) = fake_radio();
+    let mut handler = Service::build_with_config(stream, config).await?;
+    radio.boot(ME, &[(ME, "me"), (PEER, "peer")]).await?;
+    handler.wait_for_boot_ready(5).await?;
+    Ok((handler, radio))
+}
+
+/// First status of the handler matching `accept`, the previous ones are dropped.
+pub async fn wait_for_status<F>(handler: &mut Handler, mut accept: F) -> Result<Status>
+where
+    F: FnMut(&Status) -> bool,
+{
+    loop {
+        match tokio::time::timeout(Duration::from_secs(5), handler.status_rx.recv()).await? {
+            Some(status) if accept(&status) => return Ok(status),
+            Some(_) => {}
+            None => bail!("Channel closed"),
+        }
+    }
+}
+
+pub fn user(node_num: u32, short_name: &str) -> User {
+    User {
+        id: format!("!{:08x}", node_num),
+        long_name: format!("{short_name} node"),
+        short_name: short_name.to_string(),
+        ..Default::default()
+    }
+}
+
+pub fn text_packet(id: u32, from: u32, to: u32, text: &str) -> MeshPacket {
+    data_packet(
+        id,
+        from,
+        to,
+        Data {
+            portnum: PortNum::TextMessageApp as i32,
+            payload: text.as_bytes().to_vec(),
+            ..Default::default()
+        },
+    )
+}
+
+/// Routing packet answering `request_id`, `routing::Error::None` is an ACK.
+pub fn routing_packet(from: u32, to: u32, request_id: u32, error: routing::Error) -> MeshPacket {
+    let routing = Routing {
+        variant: Some(routing::Variant::ErrorReason(error as i32)),
+    };
+    data_packet(
+        0,
+        from,
+        to,
+        Data {
+            portnum: PortNum::RoutingApp as i32,
+            payload: routing.encode_to_vec(),
+            request_id,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn data_packet(id: u32, from: u32, to: u32, data: Data) -> MeshPacket {
+    MeshPacket {
+        id,
+        from,
+        to,
+        payload_variant: Some(mesh_packet::PayloadVariant::Decoded(data)),
+        ..Default::default()
+    }
+}
+
+impl FakeRadio {
+    pub fn send(&self, payload: from_radio::PayloadVariant) {
+        let _ = self.from_radio_tx.send(FromRadio {
+            payload_variant: Some(payload),
+            ..Default::default()
+        });
+    }
+
+    pub fn send_packet(&self, packet: MeshPacket) {
+        self.send(from_radio::PayloadVariant::Packet(packet));
+    }
+
+    /// Waits for the client configuration request and answers with our node,
+    /// the NodeDB and the end of the configuration.
+    pub async fn boot(&mut self, my_node_num: u32, nodes: &[(u32, &str)]) -> Result<()> {
+        let config_id = loop {
+            let to_radio = self.next_to_radio().await?;
+            if let Some(to_radio::PayloadVariant::WantConfigId(config_id)) =
+                to_radio.payload_variant
+            {
+                break config_id;
+            }
+        };
+        self.send(from_radio::PayloadVariant::MyInfo(MyNodeInfo {
+            my_node_num,
+            ..Default::default()
+        }));
+        for (num, short_name) in nodes {
+            self.send(from_radio::PayloadVariant::NodeInfo(NodeInfo {
+                num: *num,
+                user: Some(user(*num, short_name)),
+                ..Default::default()
+            }));
+        }
+        self.send(from_radio::PayloadVariant::ConfigCompleteId(config_id));
+        Ok(())
+    }
+
+    /// Sender to inject `FromRadio` packets while the radio is borrowed elsewhere.
+    pub fn injector(&self) -> UnboundedSender<FromRadio> {
+        self.from_radio_tx.clone()
+    }
+
+    /// Next `ToRadio` written by the client, `None` once it disconnects.
+    pub async fn recv(&mut self) -> Option<ToRadio> {
+        self.to_radio_rx.recv().await
+    }
+
+    pub async fn next_to_radio(&mut self) -> Result<ToRadio> {
+        tokio::time::timeout(Duration::from_secs(10), self.recv())
+            .await?
+            .ok_or_else(|| anyhow!("Client disconnected"))
+    }
+
+    /// Next mesh packet sent by the client, skipping heartbeats and other traffic.
+    pub async fn next_packet(&mut self) -> Result<MeshPacket> {
+        loop {
+            if let Some(to_radio::PayloadVariant::Packet(packet)) =
+                self.next_to_radio().await?.payload_variant
+            {
+                return Ok(packet);
+            }
+        }
+    }
+}
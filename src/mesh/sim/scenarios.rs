@@ -0,0 +1,132 @@
+//! End to end scenarios of mbbs attached to a virtual mesh.
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+
+use super::network::{BROADCAST, Link, SimConfig, SimNetwork};
+use crate::bbs::{BBS, storage::in_memory::InMemoryStorage};
+use crate::mesh::service::{Handler, OutboxConfig, PacingConfig, ServiceConfig};
+
+const GATEWAY: u32 = 1;
+const ALICE: u32 = 2;
+const BOB: u32 = 3;
+const RELAY: u32 = 4;
+
+fn service_config(name: &str) -> ServiceConfig {
+    let path = std::env::temp_dir().join(format!("mbbs-{name}-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    ServiceConfig {
+        outbox: OutboxConfig {
+            path,
+            retry_interval: Duration::ZERO,
+            ..Default::default()
+        },
+        pacing: PacingConfig {
+            min_spacing: Duration::from_millis(100),
+            max_per_minute: 100,
+        },
+        ..Default::default()
+    }
+}
+
+async fn wait_pending(handler: &Handler, node: u32, count: usize) -> Result<()> {
+    for _ in 0..100 {
+        if handler.state.read().await.outbox.pending_for(node).len() == count {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    bail!("Expected {count} pending messages for {node}");
+}
+
+#[tokio::test]
+async fn test_dm_delivered_when_node_moves_in_range() -> Result<()> {
+    let net = SimNetwork::new(SimConfig {
+        ack_timeout: Duration::from_millis(500),
+        ..Default::default()
+    });
+    let mut alice = net.add_node(ALICE, "alice");
+    let handler = net
+        .start_gateway(GATEWAY, "bbs", service_config("store-forward"))
+        .await?;
+
+    // Alice is out of range, the message ends in the outbox
+    handler.send_text("see you", ALICE, 0).await?;
+    wait_pending(&handler, ALICE, 1).await?;
+
+    // Alice moves in range and is heard
+    net.link(GATEWAY, ALICE, Link::default());
+    alice.send_text(BROADCAST, "hello all");
+
+    assert_eq!(alice.next_text().await?, (GATEWAY, "see you".to_string()));
+    wait_pending(&handler, ALICE, 0).await?;
+
+    handler.finish().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscriber_notified_when_back_in_range() -> Result<()> {
+    let net = SimNetwork::new(SimConfig::default());
+    let mut alice = net.add_node(ALICE, "alice");
+    let mut bob = net.add_node(BOB, "bob");
+    let _relay = net.add_node(RELAY, "relay");
+    let mut handler = net
+        .start_gateway(GATEWAY, "bbs", service_config("subscriptions"))
+        .await?;
+
+    // Alice reaches the BBS through the relay, Bob directly
+    net.link(GATEWAY, RELAY, Link::default());
+    net.link(RELAY, ALICE, Link::default());
+    net.link(GATEWAY, BOB, Link::default());
+
+    let cancel = handler.cancel.clone();
+    let bbs = tokio::spawn(async move {
+        let mut bbs = BBS::new(InMemoryStorage::new());
+        bbs.init().await?;
+        bbs.serve(&mut handler).await
+    });
+
+    bob.send_text(GATEWAY, "/sub general");
+    assert_eq!(bob.next_text().await?, (GATEWAY, "Ack".to_string()));
+    net.unlink(GATEWAY, BOB);
+
+    alice.send_text(GATEWAY, "/post trail is clear");
+    assert_eq!(alice.next_text().await?, (GATEWAY, "Ack".to_string()));
+
+    net.link(GATEWAY, BOB, Link::default());
+    bob.send_text(BROADCAST, "back in range");
+    assert_eq!(
+        bob.next_text().await?,
+        (GATEWAY, "1 new in #general".to_string())
+    );
+
+    cancel.cancel();
+    bbs.await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transmissions_are_paced() -> Result<()> {
+    let net = SimNetwork::new(SimConfig::default());
+    let mut alice = net.add_node(ALICE, "alice");
+    let mut config = service_config("pacing");
+    config.pacing.min_spacing = Duration::from_secs(1);
+    let handler = net.start_gateway(GATEWAY, "bbs", config).await?;
+    net.link(GATEWAY, ALICE, Link::default());
+
+    for n in 0..3 {
+        handler.send_text(format!("msg {n}"), BROADCAST, 0).await?;
+    }
+    let mut received = Vec::new();
+    for n in 0..3 {
+        assert_eq!(alice.next_text().await?, (GATEWAY, format!("msg {n}")));
+        received.push(Instant::now());
+    }
+    for pair in received.windows(2) {
+        assert!(pair[1] - pair[0] >= Duration::from_millis(900));
+    }
+
+    handler.finish().await;
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use meshtastic::protobufs::{
+    StoreAndForward,
+    store_and_forward::{self, RequestResponse},
+};
+use serde::{Deserialize, Serialize};
+
+use super::json_file;
+
+#[derive(Debug, Clone)]
+pub struct StoreForwardConfig {
+    // Records the history and answers the clients only when enabled
+    pub enabled: bool,
+    pub path: PathBuf,
+    // Messages kept, the oldest are dropped first
+    pub max_messages: usize,
+    // History returned when the client asks for no window
+    pub window: Duration,
+    // Maximum messages returned per history request
+    pub return_max: usize,
+    // Time between the heartbeats clients discover the server with, zero disables them
+    pub heartbeat_interval: Duration,
+}
+
+impl Default for StoreForwardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("store_forward.json"),
+            max_messages: 1000,
+            window: Duration::from_secs(240 * 60),
+            return_max: 25,
+            heartbeat_interval: Duration::from_secs(900),
+        }
+    }
+}
+
+impl StoreForwardConfig {
+    /// Reads `STORE_FORWARD` (`true` enables the server), `STORE_FORWARD_FILE`,
+    /// `STORE_FORWARD_MAX_MESSAGES`, `STORE_FORWARD_WINDOW_MINS`,
+    /// `STORE_FORWARD_RETURN_MAX` and `STORE_FORWARD_HEARTBEAT_SECS`, using
+    /// the defaults for the missing ones.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        let var = |name| std::env::var(name).ok();
+        config.enabled = var("STORE_FORWARD").is_some_and(|v| v == "true" || v == "1");
+        if let Some(path) = var("STORE_FORWARD_FILE") {
+            config.path = PathBuf::from(path);
+        }
+        if let Some(max) = var("STORE_FORWARD_MAX_MESSAGES").and_then(|v| v.parse().ok()) {
+            config.max_messages = max;
+        }
+        if let Some(mins) = var("STORE_FORWARD_WINDOW_MINS").and_then(|v| v.parse::<u64>().ok()) {
+            config.window = Duration::from_secs(mins * 60);
+        }
+        if let Some(max) = var("STORE_FORWARD_RETURN_MAX").and_then(|v| v.parse().ok()) {
+            config.return_max = max;
+        }
+        if let Some(secs) = var("STORE_FORWARD_HEARTBEAT_SECS").and_then(|v| v.parse().ok()) {
+            config.heartbeat_interval = Duration::from_secs(secs);
+        }
+        config
+    }
+}
+
+/// Broadcast text message heard in the primary channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub from: u32,
+    // Unix time it was received
+    pub time: i64,
+    pub text: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct History {
+    messages: VecDeque<StoredMessage>,
+    // Unix time of the last history request of each client
+    last_requests: HashMap<u32, i64>,
+}
+
+/// Server side of the Store & Forward protocol: keeps the broadcast text
+/// history, saved to disk on every change, and replays to the clients what
+/// they missed since their last request.
+#[derive(Debug, Default)]
+pub struct StoreForward {
+    config: StoreForwardConfig,
+    history: History,
+    started: Option<Instant>,
+    messages_total: u32,
+    requests: u32,
+    requests_history: u32,
+}
+
+fn message(rr: RequestResponse, variant: store_and_forward::Variant) -> StoreAndForward {
+    StoreAndForward {
+        rr: rr as i32,
+        variant: Some(variant),
+    }
+}
+
+impl StoreForward {
+    pub fn load(config: StoreForwardConfig) -> Result<Self> {
+        let history = if config.enabled {
+            json_file::load(&config.path)
+        } else {
+            History::default()
+        };
+        Ok(Self {
+            config,
+            history,
+            started: Some(Instant::now()),
+            ..Default::default()
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        json_file::save(&self.config.path, &self.history)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Time between heartbeats, `None` when they are not sent.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        Some(self.config.heartbeat_interval)
+            .filter(|interval| self.config.enabled && !interval.is_zero())
+    }
+
+    pub fn heartbeat(&self) -> StoreAndForward {
+        message(
+            RequestResponse::RouterHeartbeat,
+            store_and_forward::Variant::Heartbeat(store_and_forward::Heartbeat {
+                period: self.config.heartbeat_interval.as_secs() as u32,
+                secondary: 0,
+            }),
+        )
+    }
+
+    /// Records a broadcast text message of the primary channel. Secondary
+    /// channels are private, so they are never replayed.
+    pub fn record(&mut self, from: u32, text: String, time: i64) -> Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        self.history
+            .messages
+            .push_back(StoredMessage { from, time, text });
+        while self.history.messages.len() > self.config.max_messages {
+            self.history.messages.pop_front();
+        }
+        self.messages_total += 1;
+        self.save()
+    }
+
+    /// Responses to a request of `client` received at `now`, messages being
+    /// replayed as `<sender>: <text>` as the radio sends them as ours.
+    pub fn handle_request(
+        &mut self,
+        client: u32,
+        request: &StoreAndForward,
+        now: i64,
+        name: impl Fn(u32) -> String,
+    ) -> Result<Vec<StoreAndForward>> {
+        if !self.config.enabled {
+            return Ok(Vec::new());
+        }
+        let responses = match RequestResponse::try_from(request.rr) {
+            Ok(RequestResponse::ClientHistory) => {
+                self.requests += 1;
+                self.requests_history += 1;
+                let window = match &request.variant {
+                    Some(store_and_forward::Variant::History(history)) if history.window > 0 => {
+                        history.window as i64 * 60
+                    }
+                    _ => self.config.window.as_secs() as i64,
+                };
+                let last_request = self.history.last_requests.get(&client).copied();
+                let since = last_request.unwrap_or(0).max(now - window);
+                let missed: Vec<&StoredMessage> = self
+                    .history
+                    .messages
+                    .iter()
+                    .filter(|msg| msg.time > since && msg.from != client)
+                    .collect();
+                let missed = &missed[missed.len().saturating_sub(self.config.return_max)..];
+
+                let mut responses = vec![message(
+                    RequestResponse::RouterHistory,
+                    store_and_forward::Variant::History(store_and_forward::History {
+                        history_messages: missed.len() as u32,
+                        window: (window * 1000) as u32,
+                        last_request: last_request.unwrap_or(0) as u32,
+                    }),
+                )];
+                responses.extend(missed.iter().map(|msg| {
+                    let text = format!("{}: {}", name(msg.from), msg.text);
+                    message(
+                        RequestResponse::RouterTextBroadcast,
+                        store_and_forward::Variant::Text(text.into_bytes()),
+                    )
+                }));
+                self.history.last_requests.insert(client, now);
+                self.save()?;
+                responses
+            }
+            Ok(RequestResponse::ClientPing) => vec![StoreAndForward {
+                rr: RequestResponse::RouterPong as i32,
+                variant: None,
+            }],
+            Ok(RequestResponse::ClientStats) => {
+                self.requests += 1;
+                vec![message(
+                    RequestResponse::RouterStats,
+                    store_and_forward::Variant::Stats(self.stats()),
+                )]
+            }
+            _ => Vec::new(),
+        };
+        Ok(responses)
+    }
+
+    fn stats(&self) -> store_and_forward::Statistics {
+        store_and_forward::Statistics {
+            messages_total: self.messages_total,
+            messages_saved: self.history.messages.len() as u32,
+            messages_max: self.config.max_messages as u32,
+            up_time: self
+                .started
+                .map_or(0, |started| started.elapsed().as_secs() as u32),
+            requests: self.requests,
+            requests_history: self.requests_history,
+            heartbeat: self.heartbeat_interval().is_some(),
+            return_max: self.config.return_max as u32,
+            return_window: (self.config.window.as_secs() / 60) as u32,
+        }
+    }
+}
+
+#[test]
+fn test_store_forward() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("mbbs-sf-{}.json", std::process::id()));
+    let config = StoreForwardConfig {
+        enabled: true,
+        path: path.clone(),
+        max_messages: 3,
+        return_max: 2,
+        ..Default::default()
+    };
+    let now = 1_700_000_000;
+    let mut store = StoreForward::load(config.clone())?;
+    for (n, from) in [0xaa, 0xbb, 0xcc, 0xaa].into_iter().enumerate() {
+        store.record(from, format!("msg {n}"), now - 600 + n as i64)?;
+    }
+    let history = |window| StoreAndForward {
+        rr: RequestResponse::ClientHistory as i32,
+        variant: Some(store_and_forward::Variant::History(
+            store_and_forward::History {
+                window,
+                ..Default::default()
+            },
+        )),
+    };
+    let texts = |responses: &[StoreAndForward]| -> Vec<String> {
+        responses
+            .iter()
+            .filter_map(|response| match &response.variant {
+                Some(store_and_forward::Variant::Text(text)) => {
+                    Some(String::from_utf8_lossy(text).into_owned())
+                }
+                _ => None,
+            })
+            .collect()
+    };
+    let name = |id: u32| format!("{id:x}");
+
+    // Reloaded from disk, "msg 0" dropped and the client own message skipped
+    let mut store = StoreForward::load(config)?;
+    let responses = store.handle_request(0xaa, &history(0), now, name)?;
+    assert_eq!(responses[0].rr, RequestResponse::RouterHistory as i32);
+    assert_eq!(texts(&responses), vec!["bb: msg 1", "cc: msg 2"]);
+    // Nothing new since the last request
+    let responses = store.handle_request(0xaa, &history(0), now + 60, name)?;
+    assert!(texts(&responses).is_empty());
+    // Window too short for the others
+    let responses = store.handle_request(0xdd, &history(5), now, name)?;
+    assert!(texts(&responses).is_empty());
+
+    let ping = StoreAndForward {
+        rr: RequestResponse::ClientPing as i32,
+        variant: None,
+    };
+    let responses = store.handle_request(0xdd, &ping, now, name)?;
+    assert_eq!(responses[0].rr, RequestResponse::RouterPong as i32);
+
+    // A damaged history is moved aside instead of failing the service
+    std::fs::write(&path, b"{\"messages\": [")?;
+    let store = StoreForward::load(store.config.clone())?;
+    assert_eq!(store.stats().messages_saved, 0);
+    std::fs::remove_file(json_file::with_suffix(&path, ".corrupt"))?;
+    Ok(())
+}
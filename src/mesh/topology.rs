@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use meshtastic::{
+    Message,
+    protobufs::{MeshPacket, NeighborInfo, NodeInfo, PortNum, RouteDiscovery, mesh_packet},
+};
+
+use super::types::Traceroute;
+
+/// How a link between two nodes was learnt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkSource {
+    // Reported by a node in its `NeighborInfo`
+    Neighbor,
+    // Consecutive hops of a traceroute
+    Traceroute,
+    // Packet heard by our radio without hops
+    Heard,
+}
+
+impl LinkSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkSource::Neighbor => "neighbor",
+            LinkSource::Traceroute => "traceroute",
+            LinkSource::Heard => "heard",
+        }
+    }
+}
+
+/// Radio link between two nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    // dB of the last report that had it
+    pub snr: Option<f32>,
+    // Unix time of the last report
+    pub last_seen: i64,
+    pub sources: BTreeSet<LinkSource>,
+}
+
+/// Undirected graph of the links between nodes, keyed by the node pair with
+/// the lowest number first.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    pub links: BTreeMap<(u32, u32), Link>,
+    // Hops between our radio and each node, as last observed
+    pub hops: BTreeMap<u32, u32>,
+}
+
+// Placeholder of the nodes a traceroute could not name
+const UNKNOWN_NODE: u32 = 0xffffffff;
+
+impl Topology {
+    pub fn add_link(&mut self, a: u32, b: u32, snr: Option<f32>, time: i64, source: LinkSource) {
+        if a == b || a == UNKNOWN_NODE || b == UNKNOWN_NODE || a == 0 || b == 0 {
+            return;
+        }
+        let link = self
+            .links
+            .entry((a.min(b), a.max(b)))
+            .or_insert_with(|| Link {
+                snr: None,
+                last_seen: time,
+                sources: BTreeSet::new(),
+            });
+        if time >= link.last_seen {
+            link.last_seen = time;
+            link.snr = snr.or(link.snr);
+        } else if link.snr.is_none() {
+            link.snr = snr;
+        }
+        link.sources.insert(source);
+    }
+
+    /// Nodes with a link or a known hop count.
+    pub fn nodes(&self) -> BTreeSet<u32> {
+        self.links
+            .keys()
+            .flat_map(|(a, b)| [*a, *b])
+            .chain(self.hops.keys().copied())
+            .collect()
+    }
+
+    /// Entry of the radio NodeDB, the nodes it heard without hops are our
+    /// neighbours.
+    pub fn add_node_info(&mut self, me: u32, node_info: &NodeInfo) {
+        if node_info.num == me {
+            return;
+        }
+        let Some(hops) = node_info.hops_away else {
+            return;
+        };
+        self.hops.insert(node_info.num, hops);
+        if hops == 0 && node_info.last_heard > 0 {
+            let snr = (node_info.snr != 0.0).then_some(node_info.snr);
+            self.add_link(
+                me,
+                node_info.num,
+                snr,
+                node_info.last_heard as i64,
+                LinkSource::Heard,
+            );
+        }
+    }
+
+    pub fn add_neighbor_info(&mut self, neighbor_info: &NeighborInfo, time: i64) {
+        for neighbor in &neighbor_info.neighbors {
+            let snr = (neighbor.snr != 0.0).then_some(neighbor.snr);
+            self.add_link(
+                neighbor_info.node_id,
+                neighbor.node_id,
+                snr,
+                time,
+                LinkSource::Neighbor,
+            );
+        }
+    }
+
+    pub fn add_traceroute(&mut self, traceroute: &Traceroute) {
+        for hops in [&traceroute.towards, &traceroute.back] {
+            for pair in hops.windows(2) {
+                self.add_link(
+                    pair[0].node,
+                    pair[1].node,
+                    pair[1].snr,
+                    traceroute.time,
+                    LinkSource::Traceroute,
+                );
+            }
+        }
+    }
+
+    /// Packet received by our radio `me` at `time`, adding the hops it took
+    /// and the links it reports.
+    pub fn add_packet(&mut self, me: u32, mesh_packet: &MeshPacket, time: i64) {
+        if mesh_packet.from == me {
+            return;
+        }
+        // Packets of firmware older than 2.3 have no hop_start
+        if mesh_packet.hop_start > 0 && mesh_packet.hop_start >= mesh_packet.hop_limit {
+            let hops = mesh_packet.hop_start - mesh_packet.hop_limit;
+            self.hops.insert(mesh_packet.from, hops);
+            if hops == 0 {
+                let snr = (mesh_packet.rx_snr != 0.0).then_some(mesh_packet.rx_snr);
+                self.add_link(me, mesh_packet.from, snr, time, LinkSource::Heard);
+            }
+        }
+        let Some(mesh_packet::PayloadVariant::Decoded(data)) = &mesh_packet.payload_variant else {
+            return;
+        };
+        match PortNum::try_from(data.portnum) {
+            Ok(PortNum::NeighborinfoApp) => {
+                if let Ok(neighbor_info) = NeighborInfo::decode(data.payload.as_slice()) {
+                    self.add_neighbor_info(&neighbor_info, time);
+                }
+            }
+            // Only the responses to our requests carry the full route
+            Ok(PortNum::TracerouteApp) if data.request_id != 0 && mesh_packet.to == me => {
+                if let Ok(route) = RouteDiscovery::decode(data.payload.as_slice()) {
+                    let traceroute =
+                        Traceroute::from_route_discovery(me, mesh_packet.from, &route, time);
+                    self.add_traceroute(&traceroute);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_topology() {
+    use meshtastic::protobufs::{Data, Neighbor};
+
+    const ME: u32 = 0x11;
+    let mut topology = Topology::default();
+
+    let neighbor_info = NeighborInfo {
+        node_id: 0x22,
+        neighbors: vec![
+            Neighbor {
+                node_id: 0x33,
+                snr: 5.5,
+                ..Default::default()
+            },
+            Neighbor {
+                node_id: 0x11,
+                snr: -2.0,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    topology.add_packet(
+        ME,
+        &MeshPacket {
+            from: 0x22,
+            to: 0xffffffff,
+            hop_start: 3,
+            hop_limit: 3,
+            rx_snr: -1.5,
+            payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+                portnum: PortNum::NeighborinfoApp as i32,
+                payload: neighbor_info.encode_to_vec(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        100,
+    );
+    // Heard directly, the neighbour report overrides the SNR of the same time
+    let link = &topology.links[&(0x11, 0x22)];
+    assert_eq!(link.snr, Some(-2.0));
+    assert_eq!(
+        link.sources,
+        BTreeSet::from([LinkSource::Neighbor, LinkSource::Heard])
+    );
+    assert_eq!(topology.links[&(0x22, 0x33)].snr, Some(5.5));
+    assert_eq!(topology.hops[&0x22], 0);
+
+    // An older report does not replace the last SNR
+    topology.add_link(0x33, 0x22, Some(1.0), 50, LinkSource::Traceroute);
+    let link = &topology.links[&(0x22, 0x33)];
+    assert_eq!((link.snr, link.last_seen), (Some(5.5), 100));
+
+    // Unknown hops of a traceroute make no link
+    let route = RouteDiscovery {
+        route: vec![0x22, 0xffffffff],
+        snr_towards: vec![8, 4, 12],
+        ..Default::default()
+    };
+    topology.add_traceroute(&Traceroute::from_route_discovery(ME, 0x44, &route, 200));
+    assert_eq!(topology.links.len(), 2);
+    assert_eq!(topology.links[&(0x11, 0x22)].snr, Some(2.0));
+    assert_eq!(topology.nodes(), BTreeSet::from([0x11, 0x22, 0x33]));
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use meshtastic::{
+    api::{ConnectedStreamApi, StreamApi, StreamHandle, state::Configured},
+    protobufs::FromRadio,
+    utils::{
+        generate_rand_id,
+        stream::{
+            BleId, available_serial_ports, build_ble_stream, build_serial_stream, build_tcp_stream,
+        },
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::mpsc::UnboundedReceiver,
+};
+
+// Port used by meshtasticd and WiFi radios
+const DEFAULT_TCP_PORT: u16 = 4403;
+// Baud rate of the radios serial console
+pub const DEFAULT_BAUD_RATE: u32 = 115200;
+
+/// How to reach the radio, parsed from `ble://<name>`, `tcp://<host>[:<port>]`,
+/// `serial://<device>[?baud=<rate>]` or a bare BLE device name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    Ble(String),
+    Tcp(String),
+    Serial { device: String, baud_rate: u32 },
+}
+
+/// `host:port` of a TCP address, adding the default port to the bare hosts
+/// and IP addresses, IPv6 ones being bracketed as in `[::1]:4403`.
+fn with_default_port(address: &str) -> String {
+    if address.parse::<SocketAddr>().is_ok() {
+        return address.to_string();
+    }
+    let ip = address.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = ip.parse::<IpAddr>() {
+        return SocketAddr::new(ip, DEFAULT_TCP_PORT).to_string();
+    }
+    if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{address}:{DEFAULT_TCP_PORT}")
+    }
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(address) = s.strip_prefix("tcp://") {
+            if address.is_empty() {
+                bail!("Missing host in '{s}'");
+            }
+            Ok(Transport::Tcp(with_default_port(address)))
+        } else if let Some(device) = s.strip_prefix("serial://") {
+            let (device, baud_rate) = match device.split_once("?baud=") {
+                Some((device, baud_rate)) => (device, baud_rate.parse()?),
+                None => (device, DEFAULT_BAUD_RATE),
+            };
+            if device.is_empty() {
+                bail!("Missing device in '{s}'");
+            }
+            Ok(Transport::Serial {
+                device: device.to_string(),
+                baud_rate,
+            })
+        } else if let Some(name) = s.strip_prefix("ble://") {
+            Ok(Transport::Ble(name.to_string()))
+        } else if s.contains("://") {
+            bail!("Unsupported transport '{s}'");
+        } else {
+            Ok(Transport::Ble(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Ble(name) => write!(f, "ble://{name}"),
+            Transport::Tcp(address) => write!(f, "tcp://{address}"),
+            Transport::Serial { device, baud_rate } => {
+                write!(f, "serial://{device}?baud={baud_rate}")
+            }
+        }
+    }
+}
+
+pub type Connection = (UnboundedReceiver<FromRadio>, ConnectedStreamApi<Configured>);
+
+impl Transport {
+    /// Transport from `TRANSPORT`, or `SERIAL_DEVICE` and `SERIAL_BAUD`, or `BLE_DEVICE`.
+    pub fn from_env() -> Result<Self> {
+        if let Ok(transport) = std::env::var("TRANSPORT") {
+            return transport.parse();
+        }
+        if let Ok(device) = std::env::var("SERIAL_DEVICE") {
+            let baud_rate = match std::env::var("SERIAL_BAUD") {
+                Ok(baud_rate) => baud_rate.parse()?,
+                Err(_) => DEFAULT_BAUD_RATE,
+            };
+            return Ok(Transport::Serial { device, baud_rate });
+        }
+        Ok(Transport::Ble(std::env::var("BLE_DEVICE")?))
+    }
+
+    /// Opens the stream to the radio and asks for its configuration.
+    pub async fn connect(&self) -> Result<Connection> {
+        match self {
+            Transport::Ble(name) => {
+                let stream =
+                    build_ble_stream(&BleId::from_name(name), Duration::from_secs(5)).await?;
+                configure(stream).await
+            }
+            Transport::Tcp(address) => configure(build_tcp_stream(address.clone()).await?).await,
+            Transport::Serial { device, baud_rate } => {
+                let stream = build_serial_stream(device.clone(), Some(*baud_rate), None, None)?;
+                configure(stream).await
+            }
+        }
+    }
+}
+
+/// Serial ports that look like a radio connected by USB.
+pub fn likely_serial_ports() -> Result<Vec<String>> {
+    const PREFIXES: [&str; 6] = [
+        "/dev/ttyUSB",
+        "/dev/ttyACM",
+        "/dev/cu.usbserial",
+        "/dev/cu.usbmodem",
+        "/dev/cu.SLAB",
+        "COM",
+    ];
+    Ok(available_serial_ports()?
+        .into_iter()
+        .filter(|port| PREFIXES.iter().any(|prefix| port.starts_with(prefix)))
+        .collect())
+}
+
+pub async fn configure<S>(stream_handle: StreamHandle<S>) -> Result<Connection>
+where
+    S: AsyncReadExt + AsyncWriteExt + Send + 'static,
+{
+    let stream_api = StreamApi::new();
+    let config_id = generate_rand_id();
+
+    let (packet_rx, stream_api) = stream_api.connect(stream_handle).await;
+    let stream_api = stream_api.configure(config_id).await?;
+    Ok((packet_rx, stream_api))
+}
+
+#[test]
+fn test_parse_transport() {
+    assert_eq!(
+        "tcp://localhost:4403".parse::<Transport>().unwrap(),
+        Transport::Tcp("localhost:4403".into())
+    );
+    assert_eq!(
+        "tcp://192.168.1.20".parse::<Transport>().unwrap(),
+        Transport::Tcp("192.168.1.20:4403".into())
+    );
+    assert_eq!(
+        "tcp://fd00::20".parse::<Transport>().unwrap(),
+        Transport::Tcp("[fd00::20]:4403".into())
+    );
+    assert_eq!(
+        "tcp://[fd00::20]".parse::<Transport>().unwrap(),
+        Transport::Tcp("[fd00::20]:4403".into())
+    );
+    assert_eq!(
+        "tcp://[fd00::20]:4404".parse::<Transport>().unwrap(),
+        Transport::Tcp("[fd00::20]:4404".into())
+    );
+    assert_eq!(
+        "ble://Meshtastic_1234".parse::<Transport>().unwrap(),
+        Transport::Ble("Meshtastic_1234".into())
+    );
+    assert_eq!(
+        "Meshtastic_1234".parse::<Transport>().unwrap(),
+        Transport::Ble("Meshtastic_1234".into())
+    );
+    assert_eq!(
+        "serial:///dev/ttyUSB0".parse::<Transport>().unwrap(),
+        Transport::Serial {
+            device: "/dev/ttyUSB0".into(),
+            baud_rate: DEFAULT_BAUD_RATE
+        }
+    );
+    assert_eq!(
+        "serial://COM3?baud=921600".parse::<Transport>().unwrap(),
+        Transport::Serial {
+            device: "COM3".into(),
+            baud_rate: 921600
+        }
+    );
+    assert!("serial://?baud=9600".parse::<Transport>().is_err());
+    assert!("tcp://".parse::<Transport>().is_err());
+    assert!("udp://host".parse::<Transport>().is_err());
+}
@@ -1,7 +1,7 @@
 #[allow(dead_code)]
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use meshtastic::protobufs::routing;
+use meshtastic::protobufs::{Position, RouteDiscovery, Telemetry, mesh_packet, routing, telemetry};
 
 #[derive(Debug, Clone)]
 pub enum TextMessageStatus {
@@ -12,6 +12,73 @@ pub enum TextMessageStatus {
     RoutingError(routing::Error),
 }
 
+/// Final outcome of a confirmed send
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryResult {
+    Delivered,
+    Failed(routing::Error),
+    Timeout,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfirmConfig {
+    // Time to wait for the ACK of a transmitted message
+    pub ack_timeout: Duration,
+    // Extra attempts when a message fails or is not acknowledged
+    pub retries: u32,
+}
+
+impl Default for ConfirmConfig {
+    fn default() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(30),
+            retries: 2,
+        }
+    }
+}
+
+impl ConfirmConfig {
+    /// Reads `ACK_TIMEOUT_SECS` and `ACK_RETRIES`, using the defaults for the
+    /// missing ones.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(secs) = std::env::var("ACK_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.ack_timeout = Duration::from_secs(secs);
+        }
+        if let Some(retries) = std::env::var("ACK_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            config.retries = retries;
+        }
+        config
+    }
+}
+
+/// Priority classes of outgoing messages, from lowest to highest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessagePriority {
+    Background,
+    #[default]
+    Normal,
+    High,
+    Alert,
+}
+
+impl MessagePriority {
+    pub fn mesh_priority(self) -> mesh_packet::Priority {
+        match self {
+            MessagePriority::Background => mesh_packet::Priority::Background,
+            MessagePriority::Normal => mesh_packet::Priority::Default,
+            MessagePriority::High => mesh_packet::Priority::High,
+            MessagePriority::Alert => mesh_packet::Priority::Alert,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TextMessage {
     pub ts: Instant,
@@ -19,6 +86,11 @@ pub struct TextMessage {
     pub to: u32,
     pub text: String,
     pub status: TextMessageStatus,
+    pub priority: MessagePriority,
+    // Index of the channel, 0 being the primary one
+    pub channel: u32,
+    // Node the BBS relays it for
+    pub origin: Option<u32>,
 }
 
 impl TextMessage {
@@ -29,6 +101,9 @@ impl TextMessage {
             to,
             text,
             status: TextMessageStatus::Sent,
+            priority: MessagePriority::Normal,
+            channel: 0,
+            origin: None,
         }
     }
     pub fn recieved(from: u32, to: u32, text: String) -> Self {
@@ -38,6 +113,233 @@ impl TextMessage {
             to,
             text,
             status: TextMessageStatus::Recieved,
+            priority: MessagePriority::Normal,
+            channel: 0,
+            origin: None,
+        }
+    }
+}
+
+/// Position reported by a node
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    // Meters above sea level
+    pub altitude: Option<i32>,
+    // Unix time of the fix, or of its reception when the node has no clock
+    pub time: i64,
+}
+
+impl NodePosition {
+    /// Decoded `Position`, `None` when it has no coordinates. `rx_time` is used
+    /// when the position carries no time.
+    pub fn from_position(position: &Position, rx_time: i64) -> Option<Self> {
+        let (latitude_i, longitude_i) = (position.latitude_i?, position.longitude_i?);
+        if latitude_i == 0 && longitude_i == 0 {
+            return None;
+        }
+        Some(Self {
+            latitude: latitude_i as f64 * 1e-7,
+            longitude: longitude_i as f64 * 1e-7,
+            altitude: position.altitude,
+            time: if position.time > 0 {
+                position.time as i64
+            } else {
+                rx_time
+            },
+        })
+    }
+
+    /// Short description such as `45.46421,9.18951 122m, 5m ago`.
+    pub fn describe(&self, now: i64) -> String {
+        let altitude = match self.altitude {
+            Some(altitude) => format!(" {altitude}m"),
+            None => String::new(),
+        };
+        let age = (now - self.time).max(0);
+        let age = match age {
+            0..60 => format!("{age}s"),
+            60..3600 => format!("{}m", age / 60),
+            3600..86400 => format!("{}h", age / 3600),
+            _ => format!("{}d", age / 86400),
+        };
+        format!(
+            "{:.5},{:.5}{}, {} ago",
+            self.latitude, self.longitude, altitude, age
+        )
+    }
+}
+
+/// Device and environment metrics reported by a node, each packet carries
+/// only some of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeTelemetry {
+    // Unix time of the sample
+    pub time: i64,
+    // Percent, over 100 when powered externally
+    pub battery_level: Option<u32>,
+    pub voltage: Option<f32>,
+    // Percent of airtime used by all the nodes heard
+    pub channel_utilization: Option<f32>,
+    // Percent of airtime used by the node transmissions
+    pub air_util_tx: Option<f32>,
+    // Celsius
+    pub temperature: Option<f32>,
+}
+
+impl NodeTelemetry {
+    /// Decoded `Telemetry`, `None` for the metrics we do not track. `rx_time`
+    /// is used when the telemetry carries no time.
+    pub fn from_telemetry(telemetry: &Telemetry, rx_time: i64) -> Option<Self> {
+        let time = if telemetry.time > 0 {
+            telemetry.time as i64
+        } else {
+            rx_time
+        };
+        match telemetry.variant.as_ref()? {
+            telemetry::Variant::DeviceMetrics(metrics) => Some(Self {
+                time,
+                battery_level: metrics.battery_level,
+                voltage: metrics.voltage,
+                channel_utilization: metrics.channel_utilization,
+                air_util_tx: metrics.air_util_tx,
+                ..Default::default()
+            }),
+            telemetry::Variant::EnvironmentMetrics(metrics) => Some(Self {
+                time,
+                temperature: metrics.temperature,
+                ..Default::default()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Latest value of each metric in a history, oldest sample first.
+    pub fn latest(history: &[NodeTelemetry]) -> Option<Self> {
+        let mut latest = history.last()?.clone();
+        for sample in history.iter().rev().skip(1) {
+            latest.battery_level = latest.battery_level.or(sample.battery_level);
+            latest.voltage = latest.voltage.or(sample.voltage);
+            latest.channel_utilization = latest.channel_utilization.or(sample.channel_utilization);
+            latest.air_util_tx = latest.air_util_tx.or(sample.air_util_tx);
+            latest.temperature = latest.temperature.or(sample.temperature);
+        }
+        Some(latest)
+    }
+
+    /// Short description such as `bat 87% 4.05V chutil 12.5% airtx 1.2% 21.5C`.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(battery_level) = self.battery_level {
+            if battery_level > 100 {
+                parts.push("ext power".to_string());
+            } else {
+                parts.push(format!("bat {battery_level}%"));
+            }
+        }
+        if let Some(voltage) = self.voltage {
+            parts.push(format!("{voltage:.2}V"));
+        }
+        if let Some(channel_utilization) = self.channel_utilization {
+            parts.push(format!("chutil {channel_utilization:.1}%"));
+        }
+        if let Some(air_util_tx) = self.air_util_tx {
+            parts.push(format!("airtx {air_util_tx:.1}%"));
+        }
+        if let Some(temperature) = self.temperature {
+            parts.push(format!("{temperature:.1}C"));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Node along a traced route
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceHop {
+    // 0xffffffff when the node did not record itself
+    pub node: u32,
+    // dB the node received the traceroute with, missing for the first hop
+    pub snr: Option<f32>,
+}
+
+/// Route to a node and back, as reported by a traceroute response
+#[derive(Debug, Clone, PartialEq)]
+pub struct Traceroute {
+    // Traced node
+    pub to: u32,
+    // Unix time of the response
+    pub time: i64,
+    // From us to the traced node, both included
+    pub towards: Vec<TraceHop>,
+    // From the traced node back to us, empty for firmware older than 2.5
+    pub back: Vec<TraceHop>,
+}
+
+// SNR of the hops that did not record it
+const UNKNOWN_SNR: i32 = i8::MIN as i32;
+
+fn trace_hops(from: u32, route: &[u32], to: u32, snr: &[i32]) -> Vec<TraceHop> {
+    std::iter::once(from)
+        .chain(route.iter().copied())
+        .chain(std::iter::once(to))
+        .enumerate()
+        .map(|(index, node)| TraceHop {
+            node,
+            // The radio reports SNR in quarters of dB
+            snr: index
+                .checked_sub(1)
+                .and_then(|index| snr.get(index))
+                .filter(|snr| **snr != UNKNOWN_SNR)
+                .map(|snr| *snr as f32 / 4.0),
+        })
+        .collect()
+}
+
+impl Traceroute {
+    /// Route of the response sent by `to` to the traceroute `me` requested.
+    pub fn from_route_discovery(me: u32, to: u32, route: &RouteDiscovery, time: i64) -> Self {
+        let back = if route.route_back.is_empty() && route.snr_back.is_empty() {
+            Vec::new()
+        } else {
+            trace_hops(to, &route.route_back, me, &route.snr_back)
+        };
+        Self {
+            to,
+            time,
+            towards: trace_hops(me, &route.route, to, &route.snr_towards),
+            back,
+        }
+    }
+
+    /// Hops to the traced node, without counting us.
+    pub fn hops(&self) -> usize {
+        self.towards.len() - 1
+    }
+
+    /// Short description such as `me → relay 6.5dB → peer -3.5dB | back: peer → me 4.0dB`,
+    /// naming the nodes with `name`.
+    pub fn describe(&self, name: impl Fn(u32) -> String) -> String {
+        let path = |hops: &[TraceHop]| {
+            hops.iter()
+                .map(|hop| {
+                    let node = if hop.node == 0xffffffff {
+                        "?".to_string()
+                    } else {
+                        name(hop.node)
+                    };
+                    match hop.snr {
+                        Some(snr) => format!("{node} {snr:.1}dB"),
+                        None => node,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" → ")
+        };
+        if self.back.is_empty() {
+            path(&self.towards)
+        } else {
+            format!("{} | back: {}", path(&self.towards), path(&self.back))
         }
     }
 }
@@ -1 +1,88 @@
+use meshtastic::{
+    Message,
+    api::StreamHandle,
+    protobufs::{FromRadio, ToRadio},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+};
 
+const START1: u8 = 0x94;
+const START2: u8 = 0xc3;
+
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![
+        START1,
+        START2,
+        (payload.len() >> 8) as u8,
+        payload.len() as u8,
+    ];
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Removes the first complete frame from `buffer` and returns its payload.
+pub fn take_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let start = buffer.windows(2).position(|w| w == [START1, START2])?;
+    buffer.drain(..start);
+    if buffer.len() < 4 {
+        return None;
+    }
+    let len = ((buffer[2] as usize) << 8) | buffer[3] as usize;
+    if buffer.len() < 4 + len {
+        return None;
+    }
+    let payload = buffer[4..4 + len].to_vec();
+    buffer.drain(..4 + len);
+    Some(payload)
+}
+
+/// Radio side of a `tokio::io::duplex` pair speaking the Meshtastic stream
+/// framing. Returns the client stream to pass to `Service::build`, a sender
+/// of the `FromRadio` packets written to the client and a receiver of the
+/// `ToRadio` packets written by the client.
+pub fn duplex_radio() -> (
+    StreamHandle<DuplexStream>,
+    UnboundedSender<FromRadio>,
+    UnboundedReceiver<ToRadio>,
+) {
+    let (client, radio) = tokio::io::duplex(64 * 1024);
+    let (from_radio_tx, mut from_radio_rx) = unbounded_channel::<FromRadio>();
+    let (to_radio_tx, to_radio_rx) = unbounded_channel::<ToRadio>();
+
+    tokio::spawn(async move {
+        let (mut reader, mut writer) = tokio::io::split(radio);
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            tokio::select! {
+                read = reader.read(&mut chunk) => {
+                    let n = match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    buffer.extend_from_slice(&chunk[..n]);
+                    while let Some(payload) = take_frame(&mut buffer) {
+                        if let Ok(to_radio) = ToRadio::decode(payload.as_slice()) {
+                            let _ = to_radio_tx.send(to_radio);
+                        }
+                    }
+                }
+                from_radio = from_radio_rx.recv() => {
+                    let Some(from_radio) = from_radio else { break };
+                    let frame = encode_frame(&from_radio.encode_to_vec());
+                    if writer.write_all(&frame).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+
+    (
+        StreamHandle::from_stream(client),
+        from_radio_tx,
+        to_radio_rx,
+    )
+}
@@ -0,0 +1,275 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use chrono::{Local, TimeZone};
+use clap::{Args, Subcommand, ValueEnum};
+use serde_json::{Value, json};
+
+use crate::mesh::service::{NodeDb, NodeDbConfig, NodeRecord, Service, ServiceConfig, Transport};
+use crate::positions::rfc3339;
+use crate::utils::{csv_field, node_id, parse_node_id};
+
+const CSV_HEADER: &str =
+    "id,short_name,long_name,hw_model,role,first_heard,last_heard,snr,rssi,hops_away";
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct Source {
+    /// Node database file, defaults to `NODEDB_FILE` or `nodes.json`
+    #[arg(long)]
+    db: Option<PathBuf>,
+    /// Read the nodes from this radio instead of the node database
+    #[arg(long)]
+    transport: Option<Transport>,
+    /// Seconds to keep listening to the radio after it is ready
+    #[arg(long, default_value = "0")]
+    listen: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct Filters {
+    /// Only nodes heard within this many hours
+    #[arg(long)]
+    heard_within: Option<u64>,
+    /// Only nodes last heard directly with at least this SNR in dB
+    #[arg(long, allow_negative_numbers = true)]
+    min_snr: Option<f32>,
+    /// Only nodes of this hardware model, e.g. `HELTEC_V3`
+    #[arg(long)]
+    hw_model: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NodesCommand {
+    /// Print the nodes, last heard first
+    List {
+        #[command(flatten)]
+        source: Source,
+        #[command(flatten)]
+        filters: Filters,
+    },
+    /// Print everything known about a node, by short name or `!aabbccdd`
+    Show {
+        node: String,
+        #[command(flatten)]
+        source: Source,
+    },
+    /// Write the nodes as CSV or JSON, last heard first
+    Export {
+        #[command(flatten)]
+        source: Source,
+        #[command(flatten)]
+        filters: Filters,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: Format,
+        /// File to write, stdout when missing
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+impl Source {
+    /// Nodes of the radio or the node database, last heard first.
+    async fn nodes(&self) -> Result<Vec<NodeRecord>> {
+        let mut nodes: Vec<NodeRecord> = match &self.transport {
+            Some(transport) => {
+                let config = ServiceConfig::one_shot();
+                let mut handler = Service::from_transport_with_config(transport, config).await?;
+                handler.wait_for_boot_ready(30).await?;
+                tokio::time::sleep(Duration::from_secs(self.listen)).await;
+                let nodes = handler.state.read().await.nodes.iter().cloned().collect();
+                handler.finish().await;
+                nodes
+            }
+            None => {
+                let mut config = NodeDbConfig::from_env();
+                if let Some(db) = &self.db {
+                    config.path = Some(db.clone());
+                }
+                NodeDb::load(config)?.iter().cloned().collect()
+            }
+        };
+        nodes.sort_by_key(|node| (-node.last_heard, node.num));
+        Ok(nodes)
+    }
+}
+
+impl Filters {
+    fn matches(&self, node: &NodeRecord, now: i64) -> bool {
+        self.heard_within
+            .is_none_or(|hours| node.last_heard >= now - hours as i64 * 3600)
+            && self
+                .min_snr
+                .is_none_or(|min_snr| node.snr.is_some_and(|snr| snr >= min_snr))
+            && self.hw_model.as_ref().is_none_or(|hw_model| {
+                node.hw_model()
+                    .is_some_and(|model| model.eq_ignore_ascii_case(hw_model))
+            })
+    }
+}
+
+fn local_time(time: i64) -> String {
+    match Local.timestamp_opt(time, 0).single() {
+        Some(time) if time.timestamp() > 0 => time.format("%Y-%m-%d %H:%M").to_string(),
+        _ => "-".to_string(),
+    }
+}
+
+/// One line per node with its names, hardware, role, last heard time, SNR and hops.
+pub fn print_list<'a>(nodes: impl IntoIterator<Item = &'a NodeRecord>) {
+    for node in nodes {
+        let snr = node.snr.map(|snr| format!("{snr:.1}dB"));
+        let hops = node.hops_away.map(|hops| format!("{hops} hops"));
+        println!(
+            "{:<6} {} {:<16} {:<12} {} {} {}",
+            node.short_name().unwrap_or("?"),
+            node_id(node.num),
+            node.hw_model().unwrap_or("-"),
+            node.role().unwrap_or("-"),
+            local_time(node.last_heard),
+            snr.as_deref().unwrap_or("-"),
+            hops.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+fn node_json(node: &NodeRecord) -> Value {
+    let time = |time: i64| (time > 0).then(|| rfc3339(time));
+    json!({
+        "id": node_id(node.num),
+        "short_name": node.short_name(),
+        "long_name": node.user.as_ref().map(|user| &user.long_name),
+        "hw_model": node.hw_model(),
+        "role": node.role(),
+        "first_heard": time(node.first_heard),
+        "last_heard": time(node.last_heard),
+        "snr": node.snr,
+        "rssi": node.rssi,
+        "hops_away": node.hops_away,
+    })
+}
+
+fn to_csv(nodes: &[&NodeRecord]) -> String {
+    let mut csv = format!("{CSV_HEADER}\n");
+    for node in nodes {
+        let node = node_json(node);
+        let row: Vec<String> = CSV_HEADER
+            .split(',')
+            .map(|column| csv_field(&node[column]))
+            .collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn show(nodes: &[NodeRecord], node: &str) -> Result<()> {
+    let found = match parse_node_id(node) {
+        Ok(num) if node.starts_with('!') => nodes.iter().find(|n| n.num == num),
+        _ => nodes.iter().find(|n| n.short_name() == Some(node)),
+    };
+    let Some(found) = found else {
+        bail!("Node '{node}' not found");
+    };
+    if let Value::Object(fields) = node_json(found) {
+        for (key, value) in fields {
+            let value = match value {
+                Value::String(text) => text,
+                Value::Null => "-".to_string(),
+                other => other.to_string(),
+            };
+            println!("{:<12} {}", key, value);
+        }
+    }
+    if let Some(user) = &found.user {
+        println!("{:<12} {}", "licensed", user.is_licensed);
+        println!("{:<12} {}", "public_key", !user.public_key.is_empty());
+    }
+    Ok(())
+}
+
+pub async fn nodes(command: NodesCommand) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    match command {
+        NodesCommand::List { source, filters } => {
+            let nodes = source.nodes().await?;
+            print_list(nodes.iter().filter(|node| filters.matches(node, now)));
+        }
+        NodesCommand::Show { node, source } => show(&source.nodes().await?, &node)?,
+        NodesCommand::Export {
+            source,
+            filters,
+            format,
+            output,
+        } => {
+            let nodes = source.nodes().await?;
+            let nodes: Vec<_> = nodes
+                .iter()
+                .filter(|node| filters.matches(node, now))
+                .collect();
+            let document = match format {
+                Format::Csv => to_csv(&nodes),
+                Format::Json => {
+                    let nodes: Vec<Value> = nodes.iter().map(|node| node_json(node)).collect();
+                    serde_json::to_string_pretty(&nodes)?
+                }
+            };
+            match output {
+                Some(path) => std::fs::write(path, document)?,
+                None => print!("{}", document),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_filters_and_csv() {
+    use meshtastic::protobufs::{HardwareModel, User};
+
+    let node = |num, last_heard, snr, hw_model: HardwareModel| NodeRecord {
+        num,
+        user: Some(User {
+            short_name: format!("n{num}"),
+            long_name: "Hill, top".into(),
+            hw_model: hw_model as i32,
+            ..Default::default()
+        }),
+        first_heard: last_heard,
+        last_heard,
+        snr,
+        ..Default::default()
+    };
+    let now = 1_700_000_000;
+    let recent = node(1, now - 600, Some(5.0), HardwareModel::HeltecV3);
+    let old = node(2, now - 3 * 3600, Some(-8.0), HardwareModel::Tbeam);
+
+    let filters = Filters {
+        heard_within: Some(1),
+        min_snr: None,
+        hw_model: None,
+    };
+    assert!(filters.matches(&recent, now) && !filters.matches(&old, now));
+    let filters = Filters {
+        heard_within: None,
+        min_snr: Some(-5.0),
+        hw_model: Some("heltec_v3".into()),
+    };
+    assert!(filters.matches(&recent, now) && !filters.matches(&old, now));
+
+    let csv = to_csv(&[&recent]);
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some(CSV_HEADER));
+    assert_eq!(
+        lines.next(),
+        Some(
+            "!00000001,n1,\"Hill, top\",HELTEC_V3,CLIENT,2023-11-14T22:03:20Z,2023-11-14T22:03:20Z,5.0,,"
+        )
+    );
+}
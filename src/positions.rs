@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use clap::{Args, ValueEnum};
+use meshtastic::{
+    Message,
+    protobufs::{PortNum, Position, User, from_radio, mesh_packet},
+};
+use serde_json::{Value, json};
+
+use crate::capture;
+use crate::mesh::service::{NodePosition, Service, ServiceConfig, Transport};
+use crate::utils::node_id;
+
+/// Positions of each node, oldest first.
+pub type Tracks = BTreeMap<u32, Vec<NodePosition>>;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Gpx,
+    Geojson,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Paths to the CBOR files, the positions known by the radio are exported without them
+    files: Vec<PathBuf>,
+    /// Radio to read the positions from, defaults to the environment configuration
+    #[arg(long)]
+    transport: Option<Transport>,
+    /// Seconds to keep listening for positions after the radio is ready
+    #[arg(long, default_value = "0")]
+    listen: u64,
+    #[arg(long, value_enum, default_value = "gpx")]
+    format: Format,
+    /// File to write, stdout when missing
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+fn push(tracks: &mut Tracks, node_num: u32, position: NodePosition) {
+    let track = tracks.entry(node_num).or_default();
+    if track.last() != Some(&position) {
+        track.push(position);
+    }
+}
+
+/// Tracks and short names found in capture files.
+pub fn from_captures(files: &[PathBuf]) -> Result<(Tracks, HashMap<u32, String>)> {
+    let mut tracks = Tracks::new();
+    let mut names = HashMap::new();
+    for path in files {
+        for record in capture::open(path)? {
+            let record = record?;
+            let rx_time = record.ts / 1000;
+            match &record.from_radio.payload_variant {
+                Some(from_radio::PayloadVariant::NodeInfo(node_info)) => {
+                    if let Some(user) = &node_info.user {
+                        names.insert(node_info.num, user.short_name.clone());
+                    }
+                    if let Some(position) = node_info
+                        .position
+                        .as_ref()
+                        .and_then(|p| NodePosition::from_position(p, node_info.last_heard as i64))
+                    {
+                        push(&mut tracks, node_info.num, position);
+                    }
+                }
+                Some(from_radio::PayloadVariant::Packet(mesh_packet)) => {
+                    let Some(mesh_packet::PayloadVariant::Decoded(data)) =
+                        &mesh_packet.payload_variant
+                    else {
+                        continue;
+                    };
+                    match PortNum::try_from(data.portnum) {
+                        Ok(PortNum::PositionApp) => {
+                            if let Some(position) = Position::decode(data.payload.as_slice())
+                                .ok()
+                                .and_then(|p| NodePosition::from_position(&p, rx_time))
+                            {
+                                push(&mut tracks, mesh_packet.from, position);
+                            }
+                        }
+                        Ok(PortNum::NodeinfoApp) => {
+                            if let Ok(user) = User::decode(data.payload.as_slice()) {
+                                names.insert(mesh_packet.from, user.short_name);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    for track in tracks.values_mut() {
+        track.sort_by_key(|position| position.time);
+    }
+    Ok((tracks, names))
+}
+
+/// Tracks and short names known by the radio, after listening `listen` more.
+pub async fn from_radio(
+    transport: &Transport,
+    listen: Duration,
+) -> Result<(Tracks, HashMap<u32, String>)> {
+    let mut handler =
+        Service::from_transport_with_config(transport, ServiceConfig::one_shot()).await?;
+    handler.wait_for_boot_ready(30).await?;
+    tokio::time::sleep(listen).await;
+
+    let (tracks, names) = {
+        let state = handler.state.read().await;
+        let tracks = state
+            .positions
+            .iter()
+            .map(|(id, history)| (*id, history.clone()))
+            .collect();
+        let names = state
+            .nodes
+            .users()
+            .map(|(id, user)| (id, user.short_name.clone()))
+            .collect();
+        (tracks, names)
+    };
+    handler.finish().await;
+    Ok((tracks, names))
+}
+
+fn track_name(node_num: u32, names: &HashMap<u32, String>) -> String {
+    match names.get(&node_num) {
+        Some(name) => format!("{} {}", name, node_id(node_num)),
+        None => node_id(node_num),
+    }
+}
+
+pub fn rfc3339(time: i64) -> String {
+    match Utc.timestamp_opt(time, 0).single() {
+        Some(time) => time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        None => String::new(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// GPX 1.1 document with a track per node.
+pub fn to_gpx(tracks: &Tracks, names: &HashMap<u32, String>) -> String {
+    let mut gpx = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gpx version=\"1.1\" creator=\"mbbs\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n"
+    ));
+    for (node_num, track) in tracks {
+        gpx.push_str("  <trk>\n");
+        gpx.push_str(&format!(
+            "    <name>{}</name>\n",
+            xml_escape(&track_name(*node_num, names))
+        ));
+        gpx.push_str("    <trkseg>\n");
+        for position in track {
+            gpx.push_str(&format!(
+                "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">",
+                position.latitude, position.longitude
+            ));
+            if let Some(altitude) = position.altitude {
+                gpx.push_str(&format!("<ele>{}</ele>", altitude));
+            }
+            gpx.push_str(&format!(
+                "<time>{}</time></trkpt>\n",
+                rfc3339(position.time)
+            ));
+        }
+        gpx.push_str("    </trkseg>\n  </trk>\n");
+    }
+    gpx.push_str("</gpx>\n");
+    gpx
+}
+
+/// GeoJSON feature collection with a line per node, or a point for the nodes
+/// with a single position.
+pub fn to_geojson(tracks: &Tracks, names: &HashMap<u32, String>) -> Value {
+    let features: Vec<Value> = tracks
+        .iter()
+        .filter(|(_, track)| !track.is_empty())
+        .map(|(node_num, track)| {
+            let coordinates: Vec<Value> = track
+                .iter()
+                .map(|p| match p.altitude {
+                    Some(altitude) => json!([p.longitude, p.latitude, altitude]),
+                    None => json!([p.longitude, p.latitude]),
+                })
+                .collect();
+            let geometry = if coordinates.len() == 1 {
+                json!({"type": "Point", "coordinates": coordinates[0]})
+            } else {
+                json!({"type": "LineString", "coordinates": coordinates})
+            };
+            json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": {
+                    "id": node_id(*node_num),
+                    "name": names.get(node_num),
+                    "times": track.iter().map(|p| rfc3339(p.time)).collect::<Vec<_>>(),
+                },
+            })
+        })
+        .collect();
+    json!({"type": "FeatureCollection", "features": features})
+}
+
+pub async fn export(args: ExportArgs) -> Result<()> {
+    let (tracks, names) = if args.files.is_empty() {
+        let transport = match args.transport {
+            Some(transport) => transport,
+            None => Transport::from_env()?,
+        };
+        from_radio(&transport, Duration::from_secs(args.listen)).await?
+    } else {
+        from_captures(&args.files)?
+    };
+    let document = match args.format {
+        Format::Gpx => to_gpx(&tracks, &names),
+        Format::Geojson => serde_json::to_string_pretty(&to_geojson(&tracks, &names))?,
+    };
+    match args.output {
+        Some(path) => std::fs::write(path, document)?,
+        None => print!("{}", document),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_export() {
+    let position = |latitude, longitude, time| NodePosition {
+        latitude,
+        longitude,
+        altitude: Some(120),
+        time,
+    };
+    let mut tracks = Tracks::new();
+    push(&mut tracks, 0xaa, position(45.5, 9.25, 1_700_000_000));
+    push(&mut tracks, 0xaa, position(45.5, 9.25, 1_700_000_000));
+    push(&mut tracks, 0xaa, position(45.6, 9.3, 1_700_000_060));
+    push(&mut tracks, 0xbb, position(46.0, 10.0, 1_700_000_000));
+    let names = HashMap::from([(0xaa, "A&B".to_string())]);
+
+    let gpx = to_gpx(&tracks, &names);
+    assert_eq!(gpx.matches("<trk>").count(), 2);
+    assert_eq!(gpx.matches("<trkpt").count(), 3);
+    assert!(gpx.contains("<name>A&amp;B !000000aa</name>"));
+    assert!(gpx.contains(
+        "<trkpt lat=\"45.5000000\" lon=\"9.2500000\"><ele>120</ele><time>2023-11-14T22:13:20Z</time></trkpt>"
+    ));
+
+    let geojson = to_geojson(&tracks, &names);
+    let features = geojson["features"].as_array().unwrap();
+    assert_eq!(features[0]["geometry"]["type"], "LineString");
+    assert_eq!(
+        features[0]["geometry"]["coordinates"][1],
+        json!([9.3, 45.6, 120])
+    );
+    assert_eq!(features[1]["geometry"]["type"], "Point");
+    assert_eq!(features[1]["properties"]["name"], Value::Null);
+}
@@ -3,7 +3,15 @@ use std::{io::Write, time::Duration};
 use anyhow::{Result, bail};
 use tokio::signal;
 
-use crate::mesh::service::{self, Handler, Service};
+use crate::bbs::{BBS, storage::in_memory::InMemoryStorage};
+use crate::mesh::{
+    self,
+    service::{self, Destination, Handler, Service, Transport},
+    transport::DEFAULT_BAUD_RATE,
+};
+
+// Time to wait for the response of a traceroute
+const TRACE_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub async fn dump_ble_devices() -> Result<()> {
     let devices = meshtastic::utils::stream::available_ble_devices(Duration::from_secs(2)).await?;
@@ -34,9 +42,31 @@ pub async fn ble_device_auto() -> Result<String> {
     }
 }
 
-pub async fn repl() -> Result<()> {
+async fn connect(handler: &mut Option<Handler>, transport: Transport) -> Result<()> {
+    if let Some(h) = handler.take() {
+        println!("Disconnecting from previous device...");
+        h.finish().await;
+        println!("Disconnected.");
+    }
+
+    let mut new_handler = Service::from_transport(&transport).await?;
+    println!("Using device: {}, booting..", transport);
+    if let Err(err) = new_handler.wait_for_boot_ready(30).await {
+        println!("Error: {}", err);
+    }
+
+    *handler = Some(new_handler);
+    Ok(())
+}
+
+pub async fn repl(transport: Option<Transport>) -> Result<()> {
     println!("Starting REPL. Type 'help' for commands.");
     let mut handler: Option<Handler> = None;
+    let mut bbs = BBS::new(InMemoryStorage::new());
+    bbs.init().await?;
+    if let Some(transport) = transport {
+        connect(&mut handler, transport).await?;
+    }
     loop {
         if let Some(handler) = &handler
             && let Some(short_name) = handler.state.read().await.my_short_name().await
@@ -66,19 +96,40 @@ pub async fn repl() -> Result<()> {
                         }
                     }
                 }
-                if let Some(h) = handler.take() {
-                    println!("Disconnecting from previous device...");
-                    h.finish().await;
-                    println!("Disconnected.");
+                connect(&mut handler, Transport::Ble(device_name)).await?;
+            }
+            "serial" => {
+                if line.len() < 2 {
+                    println!("Usage: serial <device> [baud_rate]");
+                    match mesh::transport::likely_serial_ports() {
+                        Ok(ports) => ports.iter().for_each(|port| println!("- {port}")),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
                 }
-
-                let mut new_handler = Service::from_ble(&device_name).await?;
-                println!("Using device: {}, booting..", device_name);
-                if let Err(err) = new_handler.wait_for_boot_ready(30).await {
-                    println!("Error: {}", err);
+                let baud_rate = match line.get(2).map(|b| b.parse()) {
+                    None => DEFAULT_BAUD_RATE,
+                    Some(Ok(baud_rate)) => baud_rate,
+                    Some(Err(e)) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                };
+                let transport = Transport::Serial {
+                    device: line[1].to_string(),
+                    baud_rate,
+                };
+                connect(&mut handler, transport).await?;
+            }
+            "tcp" => {
+                if line.len() < 2 {
+                    println!("Usage: tcp <host[:port]>");
+                    continue;
+                }
+                match format!("tcp://{}", line[1]).parse() {
+                    Ok(transport) => connect(&mut handler, transport).await?,
+                    Err(e) => println!("Error: {}", e),
                 }
-
-                handler = Some(new_handler);
             }
             "listen" => {
                 if let Some(mut handler) = handler.as_mut() {
@@ -87,38 +138,115 @@ pub async fn repl() -> Result<()> {
                 }
             }
             "send" => {
-                if line.len() < 3 {
-                    println!("Usage: send <node_short_name> <message>");
+                // An optional `#<channel>`, by name or index, picks the channel
+                let (channel, line) = match line.get(1) {
+                    Some(channel) if channel.starts_with('#') => (Some(*channel), &line[2..]),
+                    _ => (None, &line[1..]),
+                };
+                if line.len() < 2 {
+                    println!("Usage: send [#<channel>] <node_short_name|all> <message>");
                     continue;
                 }
-                let short_name = line[1];
-                let message = line[2..].join(" ");
+                let short_name = line[0];
+                let message = line[1..].join(" ");
 
                 if let Some(mut handler) = handler.as_mut() {
-                    let user_id = {
+                    let (user_id, channel) = {
                         let state = handler.state.read().await;
-                        let Some(user_id) = state.get_node_id_by_short_name(short_name) else {
-                            println!("Node not found: {}", short_name);
-                            continue;
+                        let user_id = if short_name == "all" {
+                            Destination::Broadcast
+                        } else {
+                            let Some(user_id) = state.get_node_id_by_short_name(short_name) else {
+                                println!("Node not found: {}", short_name);
+                                continue;
+                            };
+                            Destination::Node(user_id)
                         };
-                        user_id
+                        let channel = match channel {
+                            Some(channel) => match state.channels.index_of(channel) {
+                                Some(index) => index,
+                                None => {
+                                    println!("Channel not found: {}", channel);
+                                    continue;
+                                }
+                            },
+                            None => 0,
+                        };
+                        (user_id, channel)
                     };
 
-                    println!("Sending message to{}...", short_name);
-                    handler.send_text(message, user_id).await?;
+                    println!("Sending message to {}...", short_name);
+                    handler.send_text(message, user_id, channel).await?;
                     listen(&mut handler, false).await?;
                 }
             }
+            "trace" => {
+                if line.len() < 2 {
+                    println!("Usage: trace <node_short_name>");
+                    continue;
+                }
+                if let Some(handler) = handler.as_mut() {
+                    let Some(node_id) = handler
+                        .state
+                        .read()
+                        .await
+                        .get_node_id_by_short_name(line[1])
+                    else {
+                        println!("Node not found: {}", line[1]);
+                        continue;
+                    };
+                    println!("Tracing route to {}...", line[1]);
+                    handler.send_traceroute(node_id).await?;
+                    trace(handler, node_id).await?;
+                }
+            }
+            "admin" => {
+                if let Some(handler) = handler.as_mut()
+                    && let Err(err) = crate::admin::repl(handler, &line[1..]).await
+                {
+                    println!("Error: {}", err);
+                }
+            }
+            "bbs" => {
+                if let Some(handler) = handler.as_mut() {
+                    println!("Serving BBS...press Ctrl+C to exit");
+                    bbs.serve(handler).await?;
+                }
+            }
+            "channels" => {
+                if let Some(handler) = handler.as_ref() {
+                    for (index, name) in handler.state.read().await.channels.iter() {
+                        println!("#{} {}", index, name);
+                    }
+                }
+            }
             "nodes" => {
                 if let Some(handler) = handler.as_ref() {
                     let state = handler.state.read().await;
-                    let mut nodes: Vec<_> = state
-                        .nodes
+                    let mut nodes: Vec<_> = state.nodes.iter().collect();
+                    nodes.sort_by_key(|node| -node.last_heard);
+                    crate::nodes::print_list(nodes);
+                }
+            }
+
+            "positions" => {
+                if let Some(handler) = handler.as_ref() {
+                    let state = handler.state.read().await;
+                    let now = chrono::Utc::now().timestamp();
+                    let mut positions: Vec<_> = state
+                        .positions
                         .iter()
-                        .map(|(_, user)| &user.short_name)
+                        .filter_map(|(id, history)| Some((*id, history.last()?, history.len())))
                         .collect();
-                    nodes.sort();
-                    println!("{:?}", nodes);
+                    positions.sort_by_key(|(_, position, _)| -position.time);
+                    for (id, position, count) in positions {
+                        let name = state
+                            .nodes
+                            .user(id)
+                            .map(|user| user.short_name.clone())
+                            .unwrap_or(format!("NodeId({})", id));
+                        println!("{} {} ({} positions)", name, position.describe(now), count);
+                    }
                 }
             }
 
@@ -145,7 +273,7 @@ pub async fn listen(handler: &mut Handler, all: bool) -> Result<()> {
                         let msg = state.msg(id).await.unwrap();
                         println!("{}", state.format_msg(&msg));
                         if state.my_node_num().await == msg.to {
-                            handler.send_text(format!("Got {}", msg.text), msg.from).await?;
+                            handler.send_text(format!("Got {}", msg.text), msg.from, msg.channel).await?;
                         }
                     },
                     service::Status::UpdatedMessage(id) => {
@@ -156,6 +284,30 @@ pub async fn listen(handler: &mut Handler, all: bool) -> Result<()> {
                     service::Status::Heartbeat(_packet_count) => {
                         println!("Heartbeat.");
                     },
+                    service::Status::NodeHeard(..) => {},
+                    service::Status::NewPosition(id) => {
+                        let state = handler.state.read().await;
+                        if let Some(position) = state.last_position(id) {
+                            let now = chrono::Utc::now().timestamp();
+                            println!("📍 NodeId({}) {}", id, position.describe(now));
+                        }
+                    },
+                    service::Status::NewTelemetry(id) => {
+                        let state = handler.state.read().await;
+                        if let Some(telemetry) = state.latest_telemetry(id) {
+                            println!("📊 NodeId({}) {}", id, telemetry.describe());
+                        }
+                    },
+                    service::Status::Traceroute(traceroute) => {
+                        let state = handler.state.read().await;
+                        println!("🛰️ {}", traceroute.describe(|id| state.node_label(id)));
+                    },
+                    service::Status::Admin(node_id, response) => {
+                        println!("🔧 NodeId({}) {:?}", node_id, response);
+                    },
+                    service::Status::QueueDepth(depth) => {
+                        println!("Outgoing queue: {depth}");
+                    },
                     service::Status::FromRadio(from_radio) => {
                         if all {
                             println!("{:?}\n", from_radio);
@@ -171,3 +323,30 @@ pub async fn listen(handler: &mut Handler, all: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// Waits for the response to a traceroute to `node_id` and prints the route.
+async fn trace(handler: &mut Handler, node_id: u32) -> Result<()> {
+    let timeout = tokio::time::sleep(TRACE_TIMEOUT);
+    tokio::pin!(timeout);
+    loop {
+        tokio::select! {
+            status = handler.status_rx.recv() => {
+                let Some(status) = status else { bail!("Channel closed"); };
+                if let service::Status::Traceroute(traceroute) = status
+                    && traceroute.to == node_id
+                {
+                    let state = handler.state.read().await;
+                    println!("🛰️ {}", traceroute.describe(|id| state.node_label(id)));
+                    break;
+                }
+            }
+            _ = &mut timeout => {
+                println!("No route found");
+                break;
+            }
+            _ = handler.cancel.cancelled() => break,
+            _ = signal::ctrl_c() => break,
+        }
+    }
+    Ok(())
+}
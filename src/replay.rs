@@ -0,0 +1,274 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+use meshtastic::protobufs::{
+    FromRadio, MeshPacket, MyNodeInfo, PortNum, from_radio, mesh_packet, to_radio,
+};
+use tokio::time::Instant;
+
+use crate::alerts::TelemetryAlerts;
+use crate::bbs::{BBS, storage::in_memory::InMemoryStorage};
+use crate::capture::{self, CaptureRecord};
+use crate::mesh::{
+    crypto::ChannelKeys,
+    service::{
+        Channels, NodeDb, NodeDbConfig, OutboxConfig, PacingConfig, Service, ServiceConfig,
+        StoreForwardConfig,
+    },
+    utils::duplex_radio,
+};
+use crate::service::forward;
+use crate::telegram::StdoutNotifier;
+
+// Replay ends when the BBS sends nothing during this time after the last packet
+const REPLAY_IDLE: Duration = Duration::from_secs(3);
+
+/// Where the replayed packets end up.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Sink {
+    /// The Telegram bridge of `mbbs start`
+    Telegram,
+    /// The BBS running on top of `mesh::service`
+    Bbs,
+}
+
+/// Records of the capture files, in the order given.
+pub fn read_records(files: &[PathBuf]) -> Result<Vec<CaptureRecord>> {
+    let mut records = Vec::new();
+    for path in files {
+        for record in capture::open(path)? {
+            records.push(record?);
+        }
+    }
+    Ok(records)
+}
+
+/// Time to wait before delivering `record` to keep the original pace.
+fn gap(last_ts: &mut Option<i64>, record: &CaptureRecord) -> Duration {
+    if record.ts <= 0 {
+        return Duration::ZERO;
+    }
+    let gap = last_ts.map_or(0, |last| (record.ts - last).max(0));
+    *last_ts = Some(record.ts);
+    Duration::from_millis(gap as u64)
+}
+
+/// Node that made the capture, from its MyInfo or else the most frequent
+/// destination of the direct messages in it.
+pub fn guess_my_node(records: &[CaptureRecord]) -> Option<u32> {
+    let my_info = records
+        .iter()
+        .find_map(|record| match &record.from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::MyInfo(my_info)) => Some(my_info.my_node_num),
+            _ => None,
+        });
+    if my_info.is_some() {
+        return my_info;
+    }
+    let mut count: HashMap<u32, usize> = HashMap::new();
+    for mesh_packet in records
+        .iter()
+        .filter_map(|record| record.mesh_packet())
+        .filter(|p| p.to != 0xffffffff)
+    {
+        *count.entry(mesh_packet.to).or_default() += 1;
+    }
+    count
+        .into_iter()
+        .max_by_key(|(_, n)| *n)
+        .map(|(node, _)| node)
+}
+
+/// The replay radio boots the service itself, the boot of the capture would
+/// restart its configuration.
+fn is_boot(record: &CaptureRecord) -> bool {
+    matches!(
+        record.from_radio.payload_variant,
+        Some(from_radio::PayloadVariant::MyInfo(_))
+            | Some(from_radio::PayloadVariant::ConfigCompleteId(_))
+    )
+}
+
+pub async fn replay(
+    files: Vec<PathBuf>,
+    sink: Sink,
+    realtime: bool,
+    node: Option<u32>,
+) -> Result<()> {
+    let records = read_records(&files)?;
+    log::info!("Replaying {} records", records.len());
+    match sink {
+        Sink::Telegram => replay_telegram(records, realtime).await,
+        Sink::Bbs => {
+            let Some(node) = node.or_else(|| guess_my_node(&records)) else {
+                bail!("Unable to guess the node of the capture, use --node");
+            };
+            let records = records.into_iter().filter(|r| !is_boot(r)).collect();
+            replay_bbs(records, realtime, node).await
+        }
+    }
+}
+
+async fn replay_telegram(records: Vec<CaptureRecord>, realtime: bool) -> Result<()> {
+    let mut bot = StdoutNotifier;
+    let mut nodes = NodeDb::default();
+    let mut alerts = TelemetryAlerts::from_env()?;
+    let keys = ChannelKeys::from_env()?;
+    let mut channels = Channels::default();
+    let mut last_ts = None;
+    for record in records {
+        let gap = gap(&mut last_ts, &record);
+        if realtime {
+            tokio::time::sleep(gap).await;
+        }
+        if let Err(err) = forward(
+            &mut bot,
+            &mut nodes,
+            &mut alerts,
+            &keys,
+            &mut channels,
+            record.from_radio,
+        )
+        .await
+        {
+            log::warn!("Error processing packet {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Feeds the records to `mesh::service` through a radio that received them
+/// as node `my_node_num`, with an in-memory BBS answering, and prints what the
+/// service transmits.
+async fn replay_bbs(records: Vec<CaptureRecord>, realtime: bool, my_node_num: u32) -> Result<()> {
+    let outbox = std::env::temp_dir().join(format!("mbbs-replay-{}.json", std::process::id()));
+    let mut config = ServiceConfig {
+        outbox: OutboxConfig {
+            path: outbox.clone(),
+            ..OutboxConfig::from_env()
+        },
+        // The nodes and messages of the capture stay out of the node database
+        // and the Store & Forward history
+        nodedb: NodeDbConfig::default(),
+        store_forward: StoreForwardConfig::default(),
+        ..ServiceConfig::from_env()
+    };
+    if !realtime {
+        config.pacing = PacingConfig {
+            min_spacing: Duration::ZERO,
+            max_per_minute: usize::MAX,
+        };
+    }
+
+    let (stream, from_radio_tx, mut to_radio_rx) = duplex_radio();
+    let mut handler = Service::build_with_config(stream, config).await?;
+
+    let config_id = loop {
+        let Some(to_radio) = to_radio_rx.recv().await else {
+            bail!("Service finished");
+        };
+        if let Some(to_radio::PayloadVariant::WantConfigId(config_id)) = to_radio.payload_variant {
+            break config_id;
+        }
+    };
+    for payload in [
+        from_radio::PayloadVariant::MyInfo(MyNodeInfo {
+            my_node_num,
+            ..Default::default()
+        }),
+        from_radio::PayloadVariant::ConfigCompleteId(config_id),
+    ] {
+        from_radio_tx.send(FromRadio {
+            payload_variant: Some(payload),
+            ..Default::default()
+        })?;
+    }
+    handler.wait_for_boot_ready(5).await?;
+
+    let cancel = handler.cancel.clone();
+    let bbs = tokio::spawn(async move {
+        let mut bbs = BBS::new(InMemoryStorage::new());
+        bbs.init().await?;
+        bbs.serve(&mut handler).await?;
+        handler.finish().await;
+        anyhow::Ok(())
+    });
+
+    let mut last_ts = None;
+    let mut records = records.into_iter();
+    let mut next = records.next();
+    if let Some(record) = &next {
+        gap(&mut last_ts, record);
+    }
+    let mut deadline = Instant::now();
+    loop {
+        tokio::select! {
+            to_radio = to_radio_rx.recv() => {
+                let Some(to_radio) = to_radio else { break };
+                if let Some(to_radio::PayloadVariant::Packet(mesh_packet)) = to_radio.payload_variant {
+                    print_sent(&mesh_packet);
+                    if next.is_none() {
+                        deadline = Instant::now() + REPLAY_IDLE;
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(deadline) => {
+                let Some(record) = next.take() else { break };
+                from_radio_tx.send(record.from_radio)?;
+                next = records.next();
+                deadline = Instant::now() + match &next {
+                    Some(record) => {
+                        let gap = gap(&mut last_ts, record);
+                        if realtime { gap } else { Duration::ZERO }
+                    }
+                    None => REPLAY_IDLE,
+                };
+            }
+        }
+    }
+
+    cancel.cancel();
+    bbs.await??;
+    let _ = std::fs::remove_file(outbox);
+    Ok(())
+}
+
+fn print_sent(mesh_packet: &MeshPacket) {
+    if let Some(mesh_packet::PayloadVariant::Decoded(data)) = &mesh_packet.payload_variant
+        && data.portnum == PortNum::TextMessageApp as i32
+    {
+        println!(
+            "[bbs] → {} : {}",
+            mesh_packet.to,
+            String::from_utf8_lossy(&data.payload)
+        );
+    }
+}
+
+#[test]
+fn test_replay_order() {
+    let record = |ts, to| CaptureRecord {
+        ts,
+        ..CaptureRecord::new(
+            "",
+            FromRadio {
+                payload_variant: Some(from_radio::PayloadVariant::Packet(MeshPacket {
+                    to,
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        )
+    };
+    let records = [
+        record(100_000, 0x11),
+        record(104_000, 0xffffffff),
+        record(110_500, 0x11),
+    ];
+    assert_eq!(guess_my_node(&records), Some(0x11));
+
+    let mut last_ts = None;
+    let gaps: Vec<_> = records.iter().map(|r| gap(&mut last_ts, r)).collect();
+    assert_eq!(gaps, [0, 4000, 6500].map(Duration::from_millis));
+}
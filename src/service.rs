@@ -1,55 +1,63 @@
-use std::{fs::File, time::Duration};
-
-use crate::{storage::Storage, telegram::TelegramBot, utils::IncomingPacket};
-use anyhow::{Result, anyhow};
-use chrono::Local;
-use meshtastic::{
-    api::StreamApi,
-    protobufs::{FromRadio, from_radio},
-    utils::stream::BleId,
+use std::time::Duration;
+
+use crate::{
+    alerts::TelemetryAlerts,
+    capture::{CaptureRecord, CaptureWriter},
+    mesh::{
+        crypto::ChannelKeys,
+        service::{Channels, NodeDb, NodeTelemetry, rx_time},
+        transport::Transport,
+    },
+    telegram::{Notifier, TelegramBot},
+    utils::IncomingPacket,
 };
+use anyhow::{Result, anyhow};
+use meshtastic::protobufs::{FromRadio, from_radio};
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
 pub struct Service<'a> {
     cancel: CancellationToken,
     bot: &'a mut TelegramBot,
-    storage: &'a mut Storage,
-    ble_device: String,
+    nodes: &'a mut NodeDb,
+    capture: &'a mut CaptureWriter,
+    alerts: &'a mut TelemetryAlerts,
+    keys: &'a ChannelKeys,
+    // Sent again by the radio at each connection
+    channels: Channels,
+    transport: Transport,
 }
 
 impl<'a> Service<'a> {
     pub fn new(
         cancel: CancellationToken,
         bot: &'a mut TelegramBot,
-        storage: &'a mut Storage,
-        ble_device: String,
+        nodes: &'a mut NodeDb,
+        capture: &'a mut CaptureWriter,
+        alerts: &'a mut TelemetryAlerts,
+        keys: &'a ChannelKeys,
+        transport: Transport,
     ) -> Self {
         Self {
             cancel,
             bot,
-            storage,
-            ble_device,
+            nodes,
+            capture,
+            alerts,
+            keys,
+            channels: Channels::default(),
+            transport,
         }
     }
 
     pub async fn run(&mut self) -> Result<()> {
-        log::info!("Opening BLE to meshtastic device {}...", &self.ble_device);
+        log::info!("Opening meshtastic device {}...", &self.transport);
         let _ = self
             .bot
-            .send_message(format!("Start get events from {}", &self.ble_device))
+            .send_message(format!("Start get events from {}", &self.transport))
             .await;
 
-        let ble_stream = meshtastic::utils::stream::build_ble_stream(
-            &BleId::from_name(&self.ble_device),
-            Duration::from_secs(5),
-        )
-        .await?;
-
-        let stream_api = StreamApi::new();
-        let (mut radio_rx, stream_api) = stream_api.connect(ble_stream).await;
-        let config_id = meshtastic::utils::generate_rand_id();
-        let stream_api = stream_api.configure(config_id).await?;
+        let (mut radio_rx, stream_api) = self.transport.connect().await?;
 
         let mut err = None;
 
@@ -101,42 +109,90 @@ impl<'a> Service<'a> {
 
     async fn process(&mut self, from_radio: FromRadio) -> Result<()> {
         self.save_packet(&from_radio).await?;
-
-        let incoming = from_radio.into();
-        log::info!("recv {:?}", incoming);
-
-        match incoming {
-            IncomingPacket::NodeInfo(id, user) => {
-                self.storage.insert_node(id, user);
-            }
-            IncomingPacket::TextMessage { from, to, msg } => {
-                let from = self.storage.long_name_of(from);
-                let msg = if to == 0xffffffff {
-                    format!("💬 {} : {}", from, msg)
-                } else {
-                    format!("📩 {} → {} : {} ", from, self.storage.long_name_of(to), msg)
-                };
-                self.bot.send_message(msg).await?;
-            }
-            _ => {}
-        }
-
-        Ok(())
+        forward(
+            self.bot,
+            self.nodes,
+            self.alerts,
+            self.keys,
+            &mut self.channels,
+            from_radio,
+        )
+        .await?;
+        self.nodes.save_if_due()
     }
 
     async fn save_packet(&mut self, from_radio: &FromRadio) -> Result<()> {
-        let Some(ref payload) = from_radio.payload_variant else {
-            return Ok(());
-        };
-        let from_radio::PayloadVariant::Packet(mesh_packet) = payload else {
+        if from_radio.payload_variant.is_none() {
             return Ok(());
-        };
+        }
+        let record = CaptureRecord::new(&self.transport.to_string(), from_radio.clone());
+        self.capture.write(&record)
+    }
+}
 
-        let date = Local::now().format("%Y-%m-%d").to_string();
-        let filename = format!("network.{}.cbor", date);
-        let file = File::options().create(true).append(true).open(&filename)?;
-        serde_cbor::to_writer(&file, &mesh_packet)?;
+/// Forwards the text messages heard in the mesh and the telemetry alerts to
+/// the chat, recording the nodes heard in the node database and the channels
+/// of the radio. Packets of the channels in `keys` are decrypted.
+pub async fn forward(
+    bot: &mut dyn Notifier,
+    nodes: &mut NodeDb,
+    alerts: &mut TelemetryAlerts,
+    keys: &ChannelKeys,
+    channels: &mut Channels,
+    from_radio: FromRadio,
+) -> Result<()> {
+    if channels.add_from_radio(&from_radio) {
+        return Ok(());
+    }
+    // Reception time of the packet, even when replayed or delivered late
+    let mut time = chrono::Utc::now().timestamp();
+    match &from_radio.payload_variant {
+        Some(from_radio::PayloadVariant::NodeInfo(node_info)) => nodes.add_node_info(node_info),
+        Some(from_radio::PayloadVariant::Packet(mesh_packet)) => {
+            time = rx_time(mesh_packet);
+            nodes.add_packet(mesh_packet, time);
+        }
+        _ => {}
+    }
+    let incoming = IncomingPacket::decode(from_radio, keys);
+    log::info!("recv {:?}", incoming);
 
-        Ok(())
+    match incoming {
+        IncomingPacket::NodeInfo(id, user) => {
+            nodes.set_user(id.id(), user);
+        }
+        IncomingPacket::TextMessage {
+            from,
+            to,
+            channel,
+            msg,
+        } => {
+            let from = nodes.long_name_of(from.id());
+            let channel = channels.tag(channel);
+            let msg = if to == 0xffffffff {
+                format!("💬 {}{} : {}", channel, from, msg)
+            } else {
+                format!(
+                    "📩 {}{} → {} : {} ",
+                    channel,
+                    from,
+                    nodes.long_name_of(to.id()),
+                    msg
+                )
+            };
+            bot.send_message(msg).await?;
+        }
+        IncomingPacket::Telemetry(id, telemetry) => {
+            let Some(sample) = NodeTelemetry::from_telemetry(&telemetry, time) else {
+                return Ok(());
+            };
+            for alert in alerts.check(id.id(), &sample) {
+                bot.send_message(alert.message(&nodes.long_name_of(id.id())))
+                    .await?;
+            }
+        }
+        _ => {}
     }
+
+    Ok(())
 }
@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+use clap::{Args, ValueEnum};
+use meshtastic::{
+    Message,
+    protobufs::{MeshPacket, PortNum, User, from_radio, mesh_packet},
+};
+use serde::Serialize;
+
+use crate::capture::{self, CaptureRecord};
+use crate::utils::{node_id, portnum_name};
+
+// Talkers listed in the busiest ranking
+const BUSIEST: usize = 10;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    /// Paths to the CBOR files, `.cbor.zst` files are decompressed
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value = "table")]
+    format: Format,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p10: f32,
+    pub p50: f32,
+    pub p90: f32,
+}
+
+impl Percentiles {
+    /// Nearest rank percentiles, `None` without samples.
+    fn of(mut samples: Vec<f32>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f32::total_cmp);
+        let rank = |p: usize| samples[(samples.len() * p).div_ceil(100).max(1) - 1];
+        Some(Self {
+            p10: rank(10),
+            p50: rank(50),
+            p90: rank(90),
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct NodeStats {
+    pub name: Option<String>,
+    // Distinct packets sent by the node
+    pub packets: usize,
+    // Payload bytes of those packets
+    pub bytes: usize,
+    pub snr: Option<Percentiles>,
+    pub rssi: Option<Percentiles>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub records: usize,
+    // Packets heard, including the copies rebroadcast by other nodes
+    pub packets: usize,
+    pub unique_packets: usize,
+    // Share of the packets heard that were copies of an already heard one
+    pub duplicate_ratio: f64,
+    // Share of the distinct packets that reached us through other nodes
+    pub relayed_ratio: f64,
+    pub per_node: BTreeMap<String, NodeStats>,
+    pub per_portnum: BTreeMap<String, usize>,
+    pub per_hour: BTreeMap<String, usize>,
+    pub hops: BTreeMap<String, usize>,
+    pub busiest: Vec<(String, usize)>,
+}
+
+/// Accumulates the records of the captures into `Stats`.
+#[derive(Default)]
+struct Collector {
+    stats: Stats,
+    seen: HashSet<(u32, u32)>,
+    relayed: usize,
+    names: HashMap<u32, String>,
+    snr: HashMap<u32, Vec<f32>>,
+    rssi: HashMap<u32, Vec<f32>>,
+}
+
+fn hour(ts: i64) -> String {
+    match Local.timestamp_millis_opt(ts).single() {
+        Some(time) if ts > 0 => time.format("%Y-%m-%d %H:00").to_string(),
+        _ => "-".to_string(),
+    }
+}
+
+impl Collector {
+    fn add(&mut self, record: &CaptureRecord) {
+        self.stats.records += 1;
+        match &record.from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::NodeInfo(node_info)) => {
+                if let Some(user) = &node_info.user {
+                    self.names.insert(node_info.num, user.short_name.clone());
+                }
+            }
+            Some(from_radio::PayloadVariant::Packet(mesh_packet)) => {
+                self.add_packet(record, mesh_packet)
+            }
+            _ => {}
+        }
+    }
+
+    fn add_packet(&mut self, record: &CaptureRecord, mesh_packet: &MeshPacket) {
+        self.stats.packets += 1;
+        if mesh_packet.rx_snr != 0.0 {
+            let snr = self.snr.entry(mesh_packet.from).or_default();
+            snr.push(mesh_packet.rx_snr);
+        }
+        if mesh_packet.rx_rssi != 0 {
+            let rssi = self.rssi.entry(mesh_packet.from).or_default();
+            rssi.push(mesh_packet.rx_rssi as f32);
+        }
+        if !self.seen.insert((mesh_packet.from, mesh_packet.id)) {
+            return;
+        }
+        self.stats.unique_packets += 1;
+
+        let (portnum, bytes) = match &mesh_packet.payload_variant {
+            Some(mesh_packet::PayloadVariant::Decoded(data)) => {
+                if data.portnum == PortNum::NodeinfoApp as i32
+                    && let Ok(user) = User::decode(data.payload.as_slice())
+                {
+                    self.names.insert(mesh_packet.from, user.short_name);
+                }
+                (portnum_name(data.portnum), data.payload.len())
+            }
+            Some(mesh_packet::PayloadVariant::Encrypted(payload)) => {
+                ("ENCRYPTED".to_string(), payload.len())
+            }
+            None => ("-".to_string(), 0),
+        };
+        *self.stats.per_portnum.entry(portnum).or_default() += 1;
+        *self.stats.per_hour.entry(hour(record.ts)).or_default() += 1;
+
+        // Packets of firmware older than 2.3 have no hop_start
+        let hops = if mesh_packet.hop_start > 0 {
+            let hops = mesh_packet.hop_start.saturating_sub(mesh_packet.hop_limit);
+            if hops > 0 {
+                self.relayed += 1;
+            }
+            hops.to_string()
+        } else {
+            "unknown".to_string()
+        };
+        *self.stats.hops.entry(hops).or_default() += 1;
+
+        let node = self
+            .stats
+            .per_node
+            .entry(node_id(mesh_packet.from))
+            .or_default();
+        node.packets += 1;
+        node.bytes += bytes;
+    }
+
+    fn finish(mut self) -> Stats {
+        let stats = &mut self.stats;
+        if stats.packets > 0 {
+            stats.duplicate_ratio =
+                (stats.packets - stats.unique_packets) as f64 / stats.packets as f64;
+        }
+        if stats.unique_packets > 0 {
+            stats.relayed_ratio = self.relayed as f64 / stats.unique_packets as f64;
+        }
+        for (num, samples) in self.snr {
+            let node = stats.per_node.entry(node_id(num)).or_default();
+            node.snr = Percentiles::of(samples);
+        }
+        for (num, samples) in self.rssi {
+            let node = stats.per_node.entry(node_id(num)).or_default();
+            node.rssi = Percentiles::of(samples);
+        }
+        for (num, name) in self.names {
+            if let Some(node) = stats.per_node.get_mut(&node_id(num)) {
+                node.name = Some(name);
+            }
+        }
+        let mut busiest: Vec<_> = stats
+            .per_node
+            .iter()
+            .map(|(id, node)| (id.clone(), node.packets))
+            .collect();
+        busiest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        busiest.truncate(BUSIEST);
+        stats.busiest = busiest;
+        self.stats
+    }
+}
+
+fn percentiles(p: &Option<Percentiles>) -> String {
+    match p {
+        Some(p) => format!("{:.1}/{:.1}/{:.1}", p.p10, p.p50, p.p90),
+        None => "-".to_string(),
+    }
+}
+
+fn print_counts(title: &str, counts: &BTreeMap<String, usize>) {
+    println!("\n{title}");
+    for (key, count) in counts {
+        println!("  {:<20} {:>8}", key, count);
+    }
+}
+
+fn print_table(stats: &Stats) {
+    println!("Records          {:>8}", stats.records);
+    println!("Packets heard    {:>8}", stats.packets);
+    println!("Unique packets   {:>8}", stats.unique_packets);
+    println!("Duplicates       {:>7.1}%", stats.duplicate_ratio * 100.0);
+    println!("Relayed          {:>7.1}%", stats.relayed_ratio * 100.0);
+
+    println!("\nPer node (SNR and RSSI as p10/p50/p90)");
+    println!(
+        "  {:<10} {:<6} {:>8} {:>8} {:>18} {:>20}",
+        "node", "name", "packets", "bytes", "snr", "rssi"
+    );
+    for (id, node) in &stats.per_node {
+        println!(
+            "  {:<10} {:<6} {:>8} {:>8} {:>18} {:>20}",
+            id,
+            node.name.as_deref().unwrap_or("-"),
+            node.packets,
+            node.bytes,
+            percentiles(&node.snr),
+            percentiles(&node.rssi)
+        );
+    }
+    print_counts("Per portnum", &stats.per_portnum);
+    print_counts("Per hour", &stats.per_hour);
+    print_counts("Hops", &stats.hops);
+
+    println!("\nBusiest talkers");
+    for (id, packets) in &stats.busiest {
+        println!("  {:<20} {:>8}", id, packets);
+    }
+}
+
+pub fn stats(args: StatsArgs) -> Result<()> {
+    let mut collector = Collector::default();
+    for path in &args.files {
+        for record in capture::open(path)? {
+            match record {
+                Ok(record) => collector.add(&record),
+                Err(err) => log::warn!("Skipping record of {}: {}", path.display(), err),
+            }
+        }
+    }
+    let stats = collector.finish();
+    match args.format {
+        Format::Table => print_table(&stats),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_collect() {
+    use meshtastic::protobufs::{Data, FromRadio};
+
+    let packet = |id, from, hop_limit, rx_snr| {
+        CaptureRecord::new(
+            "",
+            FromRadio {
+                payload_variant: Some(from_radio::PayloadVariant::Packet(MeshPacket {
+                    id,
+                    from,
+                    to: 0xffffffff,
+                    hop_start: 3,
+                    hop_limit,
+                    rx_snr,
+                    payload_variant: Some(mesh_packet::PayloadVariant::Decoded(Data {
+                        portnum: PortNum::TextMessageApp as i32,
+                        payload: b"hi".to_vec(),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        )
+    };
+
+    let mut collector = Collector::default();
+    collector.add(&packet(1, 0xaa, 3, 6.0));
+    // Same packet rebroadcast by a neighbour
+    collector.add(&packet(1, 0xaa, 2, 2.0));
+    collector.add(&packet(2, 0xaa, 3, 4.0));
+    collector.add(&packet(3, 0xbb, 1, -3.0));
+    let stats = collector.finish();
+
+    assert_eq!(stats.packets, 4);
+    assert_eq!(stats.unique_packets, 3);
+    assert_eq!(stats.duplicate_ratio, 0.25);
+    assert_eq!(stats.relayed_ratio, 1.0 / 3.0);
+    assert_eq!(stats.per_portnum["TEXT_MESSAGE_APP"], 3);
+    assert_eq!(stats.hops["0"], 2);
+    assert_eq!(stats.hops["2"], 1);
+
+    let aa = &stats.per_node["!000000aa"];
+    assert_eq!((aa.packets, aa.bytes), (2, 4));
+    assert_eq!(
+        aa.snr,
+        Some(Percentiles {
+            p10: 2.0,
+            p50: 4.0,
+            p90: 6.0
+        })
+    );
+    assert_eq!(stats.busiest[0], ("!000000aa".to_string(), 2));
+}
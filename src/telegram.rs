@@ -2,6 +2,12 @@ use anyhow::Result;
 use std::{collections::VecDeque, time::Instant};
 use teloxide::{Bot, prelude::*, types::ChatId};
 
+/// Destination of the messages forwarded by the bridge.
+#[async_trait::async_trait]
+pub trait Notifier: Send {
+    async fn send_message(&mut self, message: String) -> Result<()>;
+}
+
 pub struct TelegramBot {
     bot: Bot,
     chatid: ChatId,
@@ -46,3 +52,21 @@ impl TelegramBot {
         Ok(())
     }
 }
+
+#[async_trait::async_trait]
+impl Notifier for TelegramBot {
+    async fn send_message(&mut self, message: String) -> Result<()> {
+        TelegramBot::send_message(self, message).await
+    }
+}
+
+/// Stand-in for the Telegram chat that prints the messages to stdout.
+pub struct StdoutNotifier;
+
+#[async_trait::async_trait]
+impl Notifier for StdoutNotifier {
+    async fn send_message(&mut self, message: String) -> Result<()> {
+        println!("[telegram] {}", message);
+        Ok(())
+    }
+}
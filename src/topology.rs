@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use clap::{Args, ValueEnum};
+use meshtastic::{
+    Message,
+    protobufs::{PortNum, User, from_radio, mesh_packet},
+};
+use serde_json::{Value, json};
+
+use crate::mesh::service::{Service, ServiceConfig, Topology, Transport};
+use crate::positions::rfc3339;
+use crate::replay::{guess_my_node, read_records};
+use crate::utils::node_id;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    Dot,
+    Json,
+}
+
+#[derive(Debug, Args)]
+pub struct TopologyArgs {
+    /// Paths to the CBOR files, the links known by the radio are used without them
+    files: Vec<PathBuf>,
+    /// Node number of the radio that made the capture, guessed when missing
+    #[arg(long)]
+    node: Option<u32>,
+    /// Radio to read the links from, defaults to the environment configuration
+    #[arg(long)]
+    transport: Option<Transport>,
+    /// Seconds to keep listening for links after the radio is ready
+    #[arg(long, default_value = "0")]
+    listen: u64,
+    #[arg(long, value_enum, default_value = "dot")]
+    format: Format,
+    /// File to write, stdout when missing
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+/// Topology and short names found in capture files made by the radio `node`.
+pub fn from_captures(
+    files: &[PathBuf],
+    node: Option<u32>,
+) -> Result<(Topology, HashMap<u32, String>)> {
+    let records = read_records(files)?;
+    let Some(me) = node.or_else(|| guess_my_node(&records)) else {
+        bail!("Unable to guess the node of the capture, use --node");
+    };
+    let mut topology = Topology::default();
+    let mut names = HashMap::new();
+    for record in &records {
+        match &record.from_radio.payload_variant {
+            Some(from_radio::PayloadVariant::NodeInfo(node_info)) => {
+                if let Some(user) = &node_info.user {
+                    names.insert(node_info.num, user.short_name.clone());
+                }
+                topology.add_node_info(me, node_info);
+            }
+            Some(from_radio::PayloadVariant::Packet(mesh_packet)) => {
+                let rx_time = if mesh_packet.rx_time > 0 {
+                    mesh_packet.rx_time as i64
+                } else {
+                    record.ts / 1000
+                };
+                topology.add_packet(me, mesh_packet, rx_time);
+                if let Some(mesh_packet::PayloadVariant::Decoded(data)) =
+                    &mesh_packet.payload_variant
+                    && data.portnum == PortNum::NodeinfoApp as i32
+                    && let Ok(user) = User::decode(data.payload.as_slice())
+                {
+                    names.insert(mesh_packet.from, user.short_name);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((topology, names))
+}
+
+/// Topology and short names known by the radio, after listening `listen` more.
+pub async fn from_radio(
+    transport: &Transport,
+    listen: Duration,
+) -> Result<(Topology, HashMap<u32, String>)> {
+    let mut handler =
+        Service::from_transport_with_config(transport, ServiceConfig::one_shot()).await?;
+    handler.wait_for_boot_ready(30).await?;
+    tokio::time::sleep(listen).await;
+
+    let (topology, names) = {
+        let state = handler.state.read().await;
+        let names = state
+            .nodes
+            .users()
+            .map(|(id, user)| (id, user.short_name.clone()))
+            .collect();
+        (state.topology.clone(), names)
+    };
+    handler.finish().await;
+    Ok((topology, names))
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Graphviz graph with the hops away in the node labels and the SNR in the
+/// link labels.
+pub fn to_dot(topology: &Topology, names: &HashMap<u32, String>) -> String {
+    let mut dot = String::from("graph mesh {\n");
+    for node in topology.nodes() {
+        let mut label = dot_escape(&names.get(&node).cloned().unwrap_or_else(|| node_id(node)));
+        if let Some(hops) = topology.hops.get(&node) {
+            label.push_str(&format!("\\n{hops} hops"));
+        }
+        dot.push_str(&format!("  \"{}\" [label=\"{}\"];\n", node_id(node), label));
+    }
+    for ((a, b), link) in &topology.links {
+        let snr = match link.snr {
+            Some(snr) => format!("{snr:.1}dB"),
+            None => String::new(),
+        };
+        dot.push_str(&format!(
+            "  \"{}\" -- \"{}\" [label=\"{}\", tooltip=\"last seen {}\"];\n",
+            node_id(*a),
+            node_id(*b),
+            snr,
+            rfc3339(link.last_seen)
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn to_json(topology: &Topology, names: &HashMap<u32, String>) -> Value {
+    let nodes: Vec<Value> = topology
+        .nodes()
+        .into_iter()
+        .map(|node| {
+            json!({
+                "id": node_id(node),
+                "name": names.get(&node),
+                "hops": topology.hops.get(&node),
+            })
+        })
+        .collect();
+    let links: Vec<Value> = topology
+        .links
+        .iter()
+        .map(|((a, b), link)| {
+            json!({
+                "a": node_id(*a),
+                "b": node_id(*b),
+                "snr": link.snr,
+                "last_seen": rfc3339(link.last_seen),
+                "sources": link.sources.iter().map(|s| s.name()).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({"nodes": nodes, "links": links})
+}
+
+pub async fn topology(args: TopologyArgs) -> Result<()> {
+    let (topology, names) = if args.files.is_empty() {
+        let transport = match args.transport {
+            Some(transport) => transport,
+            None => Transport::from_env()?,
+        };
+        from_radio(&transport, Duration::from_secs(args.listen)).await?
+    } else {
+        from_captures(&args.files, args.node)?
+    };
+    let document = match args.format {
+        Format::Dot => to_dot(&topology, &names),
+        Format::Json => serde_json::to_string_pretty(&to_json(&topology, &names))?,
+    };
+    match args.output {
+        Some(path) => std::fs::write(path, document)?,
+        None => print!("{}", document),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_output() {
+    use crate::mesh::service::LinkSource;
+
+    let mut topology = Topology::default();
+    topology.add_link(0xbb, 0xaa, Some(6.5), 1_700_000_000, LinkSource::Neighbor);
+    topology.add_link(0xbb, 0xcc, None, 1_700_000_000, LinkSource::Traceroute);
+    topology.hops.insert(0xbb, 1);
+    let names = HashMap::from([(0xaa, "A\"1".to_string())]);
+
+    let dot = to_dot(&topology, &names);
+    assert!(dot.contains("  \"!000000aa\" [label=\"A\\\"1\"];\n"));
+    assert!(dot.contains("  \"!000000bb\" [label=\"!000000bb\\n1 hops\"];\n"));
+    assert!(dot.contains(
+        "  \"!000000aa\" -- \"!000000bb\" [label=\"6.5dB\", tooltip=\"last seen 2023-11-14T22:13:20Z\"];\n"
+    ));
+
+    let json = to_json(&topology, &names);
+    assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+    assert_eq!(json["nodes"][1]["hops"], 1);
+    assert_eq!(json["links"][1]["snr"], Value::Null);
+    assert_eq!(json["links"][0]["sources"], json!(["neighbor"]));
+}
@@ -1,10 +1,14 @@
 use std::borrow::Cow;
 
+use anyhow::Result;
 use meshtastic::{
     Message,
-    protobufs::{Data, FromRadio, MyNodeInfo, PortNum, User, from_radio, mesh_packet},
+    protobufs::{Data, FromRadio, MyNodeInfo, PortNum, Telemetry, User, from_radio, mesh_packet},
     types::NodeId,
 };
+use serde_json::Value;
+
+use crate::mesh::crypto::ChannelKeys;
 
 #[derive(Debug)]
 pub enum IncomingPacket {
@@ -13,15 +17,30 @@ pub enum IncomingPacket {
     #[allow(unused)]
     RoutingApp(Data),
     NodeInfo(NodeId, User),
+    Telemetry(NodeId, Telemetry),
     TextMessage {
         from: NodeId,
         to: NodeId,
+        // Index of the channel, 0 being the primary one
+        channel: u32,
         msg: String,
     },
     #[allow(unused)]
     Other(Cow<'static, str>),
 }
 
+impl IncomingPacket {
+    /// Like `from`, decrypting first the packets of the channels in `keys`.
+    pub fn decode(mut from_radio: FromRadio, keys: &ChannelKeys) -> Self {
+        if let Some(from_radio::PayloadVariant::Packet(mesh_packet)) =
+            &mut from_radio.payload_variant
+        {
+            keys.decrypt_in_place(mesh_packet);
+        }
+        from_radio.into()
+    }
+}
+
 impl From<FromRadio> for IncomingPacket {
     fn from(from_radio: FromRadio) -> Self {
         use IncomingPacket::*;
@@ -52,9 +71,19 @@ impl From<FromRadio> for IncomingPacket {
                         TextMessage {
                             from: NodeId::new(mesh_packet.from),
                             to: NodeId::new(mesh_packet.to),
+                            channel: mesh_packet.channel,
                             msg,
                         }
                     }
+                    Ok(PortNum::TelemetryApp) => {
+                        // `Telemetry` is also a variant of `IncomingPacket` here
+                        let telemetry =
+                            meshtastic::protobufs::Telemetry::decode(data.payload.as_slice());
+                        match telemetry {
+                            Ok(telemetry) => Telemetry(NodeId::new(mesh_packet.from), telemetry),
+                            Err(_) => Other(Cow::Borrowed("Invalid telemetry")),
+                        }
+                    }
                     Ok(PortNum::NodeinfoApp) => {
                         if let Ok(user) = User::decode(data.payload.as_slice()) {
                             NodeInfo(NodeId::new(mesh_packet.from), user)
@@ -79,3 +108,38 @@ impl From<FromRadio> for IncomingPacket {
         }
     }
 }
+
+/// Node number from `!aabbccdd` or its decimal form.
+pub fn parse_node_id(s: &str) -> Result<u32> {
+    match s.strip_prefix('!') {
+        Some(hex) => Ok(u32::from_str_radix(hex, 16)?),
+        None => Ok(s.parse()?),
+    }
+}
+
+/// `!aabbccdd` form of a node number.
+pub fn node_id(node_num: u32) -> String {
+    format!("!{:08x}", node_num)
+}
+
+/// Name of an application such as `TEXT_MESSAGE_APP`, its number when unknown.
+pub fn portnum_name(portnum: i32) -> String {
+    match PortNum::try_from(portnum) {
+        Ok(portnum) => portnum.as_str_name().to_string(),
+        Err(_) => portnum.to_string(),
+    }
+}
+
+/// CSV field, quoted when it needs to.
+pub fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}